@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::config::Facing;
+
+/// A sequence of texture atlas frames
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub frames: Vec<usize>,
+    /// how long each frame is shown, in seconds
+    pub frame_time: f32,
+    /// whether to start over after the last frame or stay on it
+    pub looping: bool,
+}
+
+impl AnimationClip {
+    pub fn new(frames: Vec<usize>, frame_time: f32) -> Self {
+        Self {
+            frames,
+            frame_time,
+            looping: true,
+        }
+    }
+
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+}
+
+/// What an entity is doing, it has a clip for each action and facing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Idle,
+    Walk,
+    Dash,
+    Hurt,
+    Die,
+}
+
+/// A clip is picked by the action and the way the entity faces
+pub type ClipKey = (Action, Facing);
+
+/// Animation clips of an entity
+#[derive(Component, Clone, Debug, Default)]
+pub struct Animations {
+    clips: HashMap<ClipKey, AnimationClip>,
+    /// frames of entities with a plain sprite, one image each
+    images: Vec<Handle<Image>>,
+}
+
+impl Animations {
+    pub fn with_clip(mut self, action: Action, facing: Facing, clip: AnimationClip) -> Self {
+        self.clips.insert((action, facing), clip);
+        self
+    }
+
+    pub fn with_images(mut self, images: Vec<Handle<Image>>) -> Self {
+        self.images = images;
+        self
+    }
+
+    pub fn get(&self, key: ClipKey) -> Option<&AnimationClip> {
+        self.clips.get(&key)
+    }
+}
+
+/// Which clip an entity is playing and how far into it it is.
+/// Gameplay systems pick the clip with [`AnimationState::play`].
+#[derive(Component, Clone, Debug)]
+pub struct AnimationState {
+    clip: ClipKey,
+    frame: usize,
+    timer: Timer,
+    /// set when the clip changes, so the first frame is shown right away
+    changed: bool,
+}
+
+impl AnimationState {
+    pub fn new(action: Action, facing: Facing) -> Self {
+        Self {
+            clip: (action, facing),
+            frame: 0,
+            timer: Timer::from_seconds(0.0, false),
+            changed: true,
+        }
+    }
+
+    /// Switches to the clip, does nothing if it's already playing
+    pub fn play(&mut self, action: Action, facing: Facing) {
+        if self.clip != (action, facing) {
+            self.clip = (action, facing);
+            self.frame = 0;
+            self.changed = true;
+        }
+    }
+}
+
+impl Default for AnimationState {
+    fn default() -> Self {
+        Self::new(Action::Idle, Facing::default())
+    }
+}
+
+/// Sprites clips can be played on
+pub trait AnimatedSprite: Component {
+    fn show(&mut self, frame: usize, animations: &Animations);
+}
+
+impl AnimatedSprite for TextureAtlasSprite {
+    fn show(&mut self, frame: usize, _: &Animations) {
+        if self.index != frame {
+            self.index = frame;
+        }
+    }
+}
+
+// plain sprites swap their image
+impl AnimatedSprite for Handle<Image> {
+    fn show(&mut self, frame: usize, animations: &Animations) {
+        if let Some(image) = animations.images.get(frame) {
+            if self != image {
+                *self = image.clone();
+            }
+        }
+    }
+}
+
+fn animation_system<S: AnimatedSprite>(
+    mut animation_query: Query<(&Animations, &mut AnimationState, &mut S)>,
+    time: Res<Time>,
+) {
+    for (animations, mut state, mut sprite) in animation_query.iter_mut() {
+        let clip = match animations.get(state.clip) {
+            Some(clip) if !clip.frames.is_empty() => clip,
+            _ => continue,
+        };
+
+        if state.changed {
+            state.changed = false;
+            state.timer = Timer::from_seconds(clip.frame_time, true);
+        } else {
+            state.timer.tick(time.delta());
+
+            for _ in 0..state.timer.times_finished_this_tick() {
+                if state.frame + 1 < clip.frames.len() {
+                    state.frame += 1;
+                } else if clip.looping {
+                    state.frame = 0;
+                }
+            }
+        }
+
+        sprite.show(
+            clip.frames[state.frame.min(clip.frames.len() - 1)],
+            animations,
+        );
+    }
+}
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            animation_system::<TextureAtlasSprite>,
+        )
+        .add_system_to_stage(CoreStage::PostUpdate, animation_system::<Handle<Image>>);
+    }
+}
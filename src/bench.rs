@@ -0,0 +1,160 @@
+// headless bullet stress test
+// run with `cargo run --release -- --bench-bullets [bullet count]`
+
+use std::time::{Duration, Instant};
+
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::config::*;
+use crate::entity::bullet::*;
+use crate::feedback::FeedbackEvent;
+use crate::simulation::SimulationPlugin;
+
+const WARMUP_FRAMES: usize = 60;
+const MEASURED_FRAMES: usize = 600;
+const BULLETS_PER_FRAME: usize = 200;
+
+/// Number of bullets the spawner tries to keep in play
+struct BulletTarget(usize);
+
+pub fn spawn_test_level(mut commands: Commands) {
+    // closed room the size of one level, with a few pillars in the middle
+    let walls = [
+        // floor, ceiling, left and right wall
+        (
+            Vec2::new(WINDOW_WIDTH / 2.0, 8.0),
+            Vec2::new(WINDOW_WIDTH / 2.0, 8.0),
+        ),
+        (
+            Vec2::new(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT - 8.0),
+            Vec2::new(WINDOW_WIDTH / 2.0, 8.0),
+        ),
+        (
+            Vec2::new(8.0, WINDOW_HEIGHT / 2.0),
+            Vec2::new(8.0, WINDOW_HEIGHT / 2.0),
+        ),
+        (
+            Vec2::new(WINDOW_WIDTH - 8.0, WINDOW_HEIGHT / 2.0),
+            Vec2::new(8.0, WINDOW_HEIGHT / 2.0),
+        ),
+        // pillars
+        (Vec2::new(160.0, 128.0), Vec2::new(16.0, 16.0)),
+        (Vec2::new(480.0, 128.0), Vec2::new(16.0, 16.0)),
+        (Vec2::new(160.0, 384.0), Vec2::new(16.0, 16.0)),
+        (Vec2::new(480.0, 384.0), Vec2::new(16.0, 16.0)),
+    ];
+
+    for (position, half_size) in walls {
+        commands
+            .spawn()
+            .insert(Collider::cuboid(half_size.x, half_size.y))
+            .insert(CollGroupsConfig::wall())
+            .insert(RigidBody::Fixed)
+            .insert_bundle(TransformBundle::from(Transform::from_xyz(
+                position.x, position.y, 0.0,
+            )));
+    }
+}
+
+fn bullet_spawner(
+    mut commands: Commands,
+    mut pool: ResMut<BulletPool>,
+    textures: Res<BulletTextures>,
+    target: Res<BulletTarget>,
+    bullet_query: Query<(), (With<Bullet>, Without<InactiveBullet>)>,
+    mut angle: Local<f32>,
+) {
+    let missing = target.0.saturating_sub(bullet_query.iter().count());
+
+    for _ in 0..missing.min(BULLETS_PER_FRAME) {
+        // sweep around the room from its centre
+        *angle += 0.1;
+        let direction = Vec2::new(angle.cos(), angle.sin());
+
+        Bullet::new(textures.player.clone()).spawn(
+            WINDOW_WIDTH / 2.0,
+            WINDOW_HEIGHT / 2.0,
+            direction,
+            &mut pool,
+            &mut commands,
+        );
+    }
+}
+
+pub fn run_bullet_bench(bullet_count: usize) {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin)
+        .add_event::<FeedbackEvent>()
+        .add_asset::<Image>()
+        .insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            ..Default::default()
+        })
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        // step the simulation by exactly one step on every update
+        .add_plugin(SimulationPlugin {
+            seed: 0,
+            headless: true,
+        })
+        // no renderer, so don't try to load textures
+        .insert_resource(BulletTextures {
+            player: Handle::default(),
+            enemy: Handle::default(),
+        })
+        .add_plugin(BulletPlugin)
+        .insert_resource(BulletTarget(bullet_count))
+        .add_startup_system(spawn_test_level)
+        .add_system(bullet_spawner);
+
+    println!(
+        "bullet bench: {} bullets, {} frames",
+        bullet_count, MEASURED_FRAMES
+    );
+
+    for _ in 0..WARMUP_FRAMES {
+        app.update();
+    }
+
+    let mut frame_times = Vec::with_capacity(MEASURED_FRAMES);
+    for _ in 0..MEASURED_FRAMES {
+        let start = Instant::now();
+        app.update();
+        frame_times.push(start.elapsed());
+    }
+
+    frame_times.sort();
+    let total: Duration = frame_times.iter().sum();
+    let percentile = |p: f32| frame_times[((frame_times.len() - 1) as f32 * p) as usize];
+
+    let active = app
+        .world
+        .query_filtered::<(), (With<Bullet>, Without<InactiveBullet>)>()
+        .iter(&app.world)
+        .count();
+    let pool = app.world.resource::<BulletPool>();
+
+    println!("active bullets: {}", active);
+    println!(
+        "bullet entities: {} spawned, {} pooled, {} shots from the pool",
+        pool.spawned,
+        pool.free_count(),
+        pool.recycled
+    );
+    println!(
+        "frame time: avg {:.2?}, min {:.2?}, p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        total / frame_times.len() as u32,
+        frame_times[0],
+        percentile(0.5),
+        percentile(0.99),
+        frame_times[frame_times.len() - 1],
+    );
+}
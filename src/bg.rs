@@ -1,18 +1,203 @@
 use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use bevy_ecs_ldtk::prelude::*;
 
-fn bg_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn_bundle(SpriteBundle {
-        texture: asset_server.load("bg.png"),
-        transform: Transform::from_scale(Vec3::new(1., 1., 0.0)),
-        // .with_translation(Vec3::new(0.0, WINDOW_HEIGHT/2.0, 0.0)),
-        ..Default::default()
-    });
+use crate::camera::CameraController;
+use crate::level_manager::{level_at, level_field};
+use crate::pixel_perfect::snap_camera_system;
+
+/// Background used by levels without a `Background` field
+pub static DEFAULT_BACKGROUND: &str = "space";
+
+/// One layer of a background
+#[derive(Clone, Debug)]
+pub struct BackgroundLayerDef {
+    pub texture: &'static str,
+    /// size of the texture, in pixels
+    pub size: Vec2,
+    /// how much the layer moves with the world, 0.0 stays with the camera, 1.0 moves with the level
+    pub parallax: Vec2,
+    pub z: f32,
+    /// tile the texture so the layer never runs out
+    pub repeat: bool,
+    pub color: Color,
+}
+
+/// Layers of the background with the name, back to front
+pub fn background_layers(name: &str) -> Vec<BackgroundLayerDef> {
+    match name {
+        "space" => vec![
+            BackgroundLayerDef {
+                texture: "bg.png",
+                size: Vec2::new(3840.0, 1344.0),
+                parallax: Vec2::new(0.1, 0.1),
+                z: -100.0,
+                repeat: true,
+                color: Color::WHITE,
+            },
+            BackgroundLayerDef {
+                texture: "bg/stars_near.png",
+                size: Vec2::new(512.0, 512.0),
+                parallax: Vec2::new(0.3, 0.3),
+                z: -90.0,
+                repeat: true,
+                color: Color::rgba(1.0, 1.0, 1.0, 0.6),
+            },
+        ],
+        "nebula" => vec![
+            BackgroundLayerDef {
+                texture: "bg/nebula.png",
+                size: Vec2::new(640.0, 512.0),
+                parallax: Vec2::new(0.05, 0.05),
+                z: -100.0,
+                repeat: true,
+                color: Color::WHITE,
+            },
+            BackgroundLayerDef {
+                texture: "bg/stars_far.png",
+                size: Vec2::new(512.0, 512.0),
+                parallax: Vec2::new(0.15, 0.15),
+                z: -95.0,
+                repeat: true,
+                color: Color::WHITE,
+            },
+            BackgroundLayerDef {
+                texture: "bg/stars_near.png",
+                size: Vec2::new(512.0, 512.0),
+                parallax: Vec2::new(0.35, 0.35),
+                z: -90.0,
+                repeat: true,
+                color: Color::WHITE,
+            },
+        ],
+        "none" => Vec::new(),
+        _ => {
+            println!("unknown background {}", name);
+            background_layers(DEFAULT_BACKGROUND)
+        }
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+struct BackgroundLayer {
+    parallax: Vec2,
+    size: Vec2,
+    repeat: bool,
+}
+
+/// Name of the background currently shown
+#[derive(Default)]
+struct ActiveBackground(Option<String>);
+
+fn spawn_background(name: &str, commands: &mut Commands, asset_server: &AssetServer) {
+    for layer in background_layers(name) {
+        // a 3x3 grid of tiles covers the view wherever the camera is
+        let offsets = match layer.repeat {
+            true => (-1..=1)
+                .flat_map(|x| (-1..=1).map(move |y| Vec2::new(x as f32, y as f32)))
+                .collect(),
+            false => vec![Vec2::ZERO],
+        };
+
+        commands
+            .spawn_bundle(SpatialBundle::from_transform(Transform::from_xyz(
+                0.0, 0.0, layer.z,
+            )))
+            .insert(BackgroundLayer {
+                parallax: layer.parallax,
+                size: layer.size,
+                repeat: layer.repeat,
+            })
+            .with_children(|parent| {
+                for offset in offsets {
+                    parent.spawn_bundle(SpriteBundle {
+                        texture: asset_server.load(layer.texture),
+                        sprite: Sprite {
+                            color: layer.color,
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation((offset * layer.size).extend(0.0)),
+                        ..Default::default()
+                    });
+                }
+            });
+    }
+}
+
+// swaps the background when the camera moves into a level with a different one
+fn background_select_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut active: ResMut<ActiveBackground>,
+    camera_query: Query<&Transform, With<CameraController>>,
+    layer_query: Query<Entity, With<BackgroundLayer>>,
+    level_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    let camera_pos = match camera_query.iter().next() {
+        Some(transform) => transform.translation.truncate(),
+        None => return,
+    };
+
+    // keep the last background in the void between levels
+    let level = match level_at(&level_query, &levels, camera_pos) {
+        Some(level) => level,
+        None if active.0.is_some() => return,
+        None => {
+            spawn_background(DEFAULT_BACKGROUND, &mut commands, &asset_server);
+            active.0 = Some(DEFAULT_BACKGROUND.to_string());
+            return;
+        }
+    };
+
+    let name = level_field(level, "Background")
+        .map(|name| name.to_lowercase())
+        .unwrap_or_else(|| DEFAULT_BACKGROUND.to_string());
+    if active.0.as_ref() == Some(&name) {
+        return;
+    }
+
+    for entity in layer_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_background(&name, &mut commands, &asset_server);
+    active.0 = Some(name);
+}
+
+fn parallax_system(
+    camera_query: Query<&Transform, (With<CameraController>, Without<BackgroundLayer>)>,
+    mut layer_query: Query<(&BackgroundLayer, &mut Transform)>,
+) {
+    let camera_pos = match camera_query.iter().next() {
+        Some(transform) => transform.translation.truncate(),
+        None => return,
+    };
+
+    for (layer, mut transform) in layer_query.iter_mut() {
+        // the part of the camera movement the layer doesn't follow
+        let mut position = camera_pos * (Vec2::ONE - layer.parallax);
+
+        // jump by whole tiles to stay around the camera
+        if layer.repeat {
+            position += ((camera_pos - position) / layer.size).round() * layer.size;
+        }
+
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
 }
 
 pub struct BgPlugin;
 
 impl Plugin for BgPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(bg_setup);
+        app.init_resource::<ActiveBackground>()
+            .add_system(background_select_system)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                parallax_system
+                    .after(snap_camera_system)
+                    .before(TransformSystem::TransformPropagate),
+            );
     }
 }
@@ -0,0 +1,203 @@
+// little endian reading and writing for the binary formats, replays and network messages
+
+use bevy::prelude::*;
+
+use crate::config::FacingMode;
+use crate::input_manager::PlayerInput;
+
+/// Reads values off the front of a byte slice
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < count {
+            return Err("data is cut short".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub fn vec2(&mut self) -> Result<Vec2, String> {
+        Ok(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    pub fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    /// Kind, then the aim weight of a blend
+    pub fn facing_mode(&mut self) -> Result<FacingMode, String> {
+        match self.u8()? {
+            0 => Ok(FacingMode::Aim),
+            1 => Ok(FacingMode::Movement),
+            2 => Ok(FacingMode::Blend {
+                aim_weight: self.f32()?,
+            }),
+            v => Err(format!("unknown facing mode {}", v)),
+        }
+    }
+
+    /// Buttons, then movement and aim
+    pub fn input(&mut self) -> Result<PlayerInput, String> {
+        let buttons = self.u8()?;
+        Ok(PlayerInput {
+            dash: buttons & 1 != 0,
+            fire: buttons & 2 != 0,
+            melee: buttons & 4 != 0,
+            parry: buttons & 8 != 0,
+            shield: buttons & 16 != 0,
+            movement: self.vec2()?,
+            aim: self.vec2()?,
+        })
+    }
+}
+
+pub fn write_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+}
+
+pub fn write_vec2(bytes: &mut Vec<u8>, vector: Vec2) {
+    write_f32(bytes, vector.x);
+    write_f32(bytes, vector.y);
+}
+
+pub fn write_vec3(bytes: &mut Vec<u8>, vector: Vec3) {
+    write_f32(bytes, vector.x);
+    write_f32(bytes, vector.y);
+    write_f32(bytes, vector.z);
+}
+
+pub fn write_facing_mode(bytes: &mut Vec<u8>, facing_mode: FacingMode) {
+    match facing_mode {
+        FacingMode::Aim => bytes.push(0),
+        FacingMode::Movement => bytes.push(1),
+        FacingMode::Blend { aim_weight } => {
+            bytes.push(2);
+            write_f32(bytes, aim_weight);
+        }
+    }
+}
+
+pub fn write_input(bytes: &mut Vec<u8>, input: &PlayerInput) {
+    bytes.push(
+        input.dash as u8
+            | (input.fire as u8) << 1
+            | (input.melee as u8) << 2
+            | (input.parry as u8) << 3
+            | (input.shield as u8) << 4,
+    );
+    write_vec2(bytes, input.movement);
+    write_vec2(bytes, input.aim);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let mut bytes = vec![7];
+        bytes.extend_from_slice(&513u16.to_le_bytes());
+        bytes.extend_from_slice(&70000u32.to_le_bytes());
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        write_f32(&mut bytes, -0.125);
+        write_vec2(&mut bytes, Vec2::new(1.5, -3.0));
+        write_vec3(&mut bytes, Vec3::new(4.0, 5.0, f32::MAX));
+
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.u8(), Ok(7));
+        assert_eq!(reader.u16(), Ok(513));
+        assert_eq!(reader.u32(), Ok(70000));
+        assert_eq!(reader.u64(), Ok(u64::MAX));
+        assert_eq!(reader.f32(), Ok(-0.125));
+        assert_eq!(reader.vec2(), Ok(Vec2::new(1.5, -3.0)));
+        assert_eq!(reader.vec3(), Ok(Vec3::new(4.0, 5.0, f32::MAX)));
+        assert!(reader.u8().is_err());
+    }
+
+    #[test]
+    fn inputs_round_trip() {
+        let inputs = [
+            PlayerInput::default(),
+            PlayerInput {
+                movement: Vec2::new(-1.0, 0.0),
+                aim: Vec2::new(300.25, -12.0),
+                dash: true,
+                fire: true,
+                melee: true,
+                parry: true,
+                shield: true,
+            },
+            PlayerInput {
+                fire: true,
+                parry: true,
+                ..Default::default()
+            },
+        ];
+
+        let mut bytes = Vec::new();
+        for input in inputs.iter() {
+            write_input(&mut bytes, input);
+        }
+
+        let mut reader = Reader::new(&bytes);
+        for input in inputs.iter() {
+            assert_eq!(reader.input().as_ref(), Ok(input));
+        }
+    }
+
+    #[test]
+    fn facing_modes_round_trip() {
+        let modes = [
+            FacingMode::Aim,
+            FacingMode::Movement,
+            FacingMode::Blend { aim_weight: 0.75 },
+        ];
+
+        let mut bytes = Vec::new();
+        for mode in modes {
+            write_facing_mode(&mut bytes, mode);
+        }
+
+        let mut reader = Reader::new(&bytes);
+        for mode in modes {
+            assert_eq!(reader.facing_mode(), Ok(mode));
+        }
+    }
+
+    #[test]
+    fn cut_short_data_is_an_error() {
+        let mut bytes = Vec::new();
+        write_input(&mut bytes, &PlayerInput::default());
+        bytes.pop();
+
+        assert!(Reader::new(&bytes).input().is_err());
+    }
+}
@@ -1,18 +1,231 @@
 use bevy::prelude::*;
+use bevy::sprite::Rect;
+use bevy_ecs_ldtk::prelude::*;
 
+use crate::input_manager::PlayerInput;
+use crate::level_manager::{level_rect_at, level_rects};
+use crate::pixel_perfect::PixelPerfect;
 use crate::player::*;
 
-fn camera_system(
-    mut camera_query: Query<(&mut Transform, &Camera2d), Without<Player>>,
-    player_query: Query<&Transform, With<Player>>,
+/// How the camera picks what to show
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CameraMode {
+    /// follow the player around
+    Follow,
+    /// show the player's room, panning over when they walk into another one
+    RoomLocked,
+}
+
+/// Makes the camera follow the players smoothly, framing all of them
+#[derive(Component, Clone, Debug)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// how quickly the camera catches up, higher is snappier
+    pub smoothing: f32,
+    /// half size of the box around the camera focus the player can move in
+    /// without moving the camera
+    pub deadzone: Vec2,
+    /// how far towards where the players aim the camera looks, as a fraction of the distance to it
+    pub look_ahead: f32,
+    /// maximum distance of the look-ahead, in pixels
+    pub max_look_ahead: f32,
+    /// keep the camera from showing anything outside the current level
+    pub clamp_to_level: bool,
+    /// how long panning to the next room takes in room-locked mode, in seconds
+    pub room_pan_time: f32,
+    /// space kept around the players when framing several of them, in pixels
+    pub frame_margin: Vec2,
+    /// how far the camera zooms out to fit every player, as a multiple of the normal view
+    pub max_zoom_out: f32,
+    /// where the camera is before effects like screen shake are added
+    position: Option<Vec2>,
+    /// point the camera follows, moved only when the player leaves the deadzone
+    focus: Option<Vec2>,
+    /// room the camera is locked to
+    room: Option<Rect>,
+    /// where the camera pans from when changing rooms
+    room_pan: Option<(Vec2, Timer)>,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Follow,
+            smoothing: 8.0,
+            deadzone: Vec2::new(24.0, 16.0),
+            look_ahead: 0.2,
+            max_look_ahead: 48.0,
+            clamp_to_level: true,
+            room_pan_time: 0.5,
+            frame_margin: Vec2::new(48.0, 48.0),
+            max_zoom_out: 2.0,
+            position: None,
+            focus: None,
+            room: None,
+            room_pan: None,
+        }
+    }
+}
+
+/// Keeps the camera centre inside the level, or centred on it if the level is smaller than the view
+fn clamp_to_rect(center: Vec2, half_view: Vec2, rect: Rect) -> Vec2 {
+    let clamp_axis = |value: f32, half: f32, min: f32, max: f32| {
+        if max - min <= half * 2.0 {
+            (min + max) / 2.0
+        } else {
+            value.clamp(min + half, max - half)
+        }
+    };
+
+    Vec2::new(
+        clamp_axis(center.x, half_view.x, rect.min.x, rect.max.x),
+        clamp_axis(center.y, half_view.y, rect.min.y, rect.max.y),
+    )
+}
+
+pub fn camera_system(
+    mut camera_query: Query<
+        (
+            &mut Transform,
+            &mut CameraController,
+            &mut OrthographicProjection,
+        ),
+        Without<Player>,
+    >,
+    player_query: Query<(&Transform, &PlayerInput), With<Player>>,
+    level_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: Res<Assets<LdtkLevel>>,
+    (keyboard_input, time): (Res<Input<KeyCode>>, Res<Time>),
+    pixel_perfect: Res<PixelPerfect>,
 ) {
-    for player_transform in player_query.iter() {
-        let x = player_transform.translation.x;
-        let y = player_transform.translation.y;
+    let positions: Vec<Vec2> = player_query
+        .iter()
+        .map(|(transform, _)| transform.translation.truncate())
+        .collect();
+    if positions.is_empty() {
+        return;
+    }
+
+    // the box around every player, the camera follows its centre
+    let players_min = positions
+        .iter()
+        .fold(Vec2::splat(f32::MAX), |a, b| a.min(*b));
+    let players_max = positions
+        .iter()
+        .fold(Vec2::splat(f32::MIN), |a, b| a.max(*b));
+    let player_pos = (players_min + players_max) / 2.0;
+
+    // where the players aim, on average
+    let aim_offset = player_query
+        .iter()
+        .map(|(transform, input)| input.aim - transform.translation.truncate())
+        .fold(Vec2::ZERO, |sum, offset| sum + offset)
+        / positions.len() as f32;
+
+    let rects = level_rects(&level_query, &levels);
 
-        for (mut transform, _) in camera_query.iter_mut() {
-            transform.translation = Vec3::new(x, y, 1000.0);
+    for (mut transform, mut controller, mut projection) in camera_query.iter_mut() {
+        // toggle room-locked camera
+        if keyboard_input.just_pressed(KeyCode::C) {
+            controller.mode = match controller.mode {
+                CameraMode::Follow => CameraMode::RoomLocked,
+                CameraMode::RoomLocked => CameraMode::Follow,
+            };
+            controller.room = None;
+            controller.room_pan = None;
         }
+
+        // zoom out just enough to keep every player in view
+        let base_half_view = Vec2::new(
+            projection.right - projection.left,
+            projection.top - projection.bottom,
+        ) / 2.0;
+        let needed = (players_max - players_min) / 2.0 + controller.frame_margin;
+        // zooming by a fraction would stretch the low resolution image unevenly
+        let zoom = match pixel_perfect.enabled {
+            true => 1.0,
+            false => (needed / base_half_view)
+                .max_element()
+                .clamp(1.0, controller.max_zoom_out),
+        };
+        // only write on changes, the projection is recalculated whenever it's changed
+        if (projection.scale - zoom).abs() > 0.001 {
+            let t = 1.0 - (-controller.smoothing * time.delta_seconds()).exp();
+            projection.scale += (zoom - projection.scale) * t;
+        } else if projection.scale != zoom {
+            projection.scale = zoom;
+        }
+        let half_view = base_half_view * projection.scale;
+        let current = controller
+            .position
+            .unwrap_or_else(|| transform.translation.truncate());
+
+        if controller.mode == CameraMode::RoomLocked {
+            if let Some(room) = level_rect_at(&rects, player_pos) {
+                // start panning when the player walks into another room
+                if !controller
+                    .room
+                    .is_some_and(|locked| locked.min == room.min && locked.max == room.max)
+                {
+                    controller.room_pan = Some((
+                        current,
+                        Timer::from_seconds(controller.room_pan_time, false),
+                    ));
+                }
+                controller.room = Some(room);
+            }
+
+            if let Some(room) = controller.room {
+                // rooms bigger than the view still scroll, but never past their edges
+                let target = clamp_to_rect(player_pos, half_view, room);
+
+                let position = match controller.room_pan.as_mut() {
+                    Some((from, timer)) => {
+                        timer.tick(time.delta());
+                        // ease in and out
+                        let t = timer.percent();
+                        let t = t * t * (3.0 - 2.0 * t);
+                        from.lerp(target, t)
+                    }
+                    None => target,
+                };
+
+                if matches!(&controller.room_pan, Some((_, timer)) if timer.finished()) {
+                    controller.room_pan = None;
+                }
+
+                controller.focus = None;
+                controller.position = Some(position);
+                transform.translation = position.extend(1000.0);
+                continue;
+            }
+        }
+
+        // move the focus only as far as needed to keep the players in the deadzone
+        let mut focus = controller.focus.unwrap_or(player_pos);
+        let offset = player_pos - focus;
+        let deadzone = controller.deadzone;
+        focus += offset - offset.clamp(-deadzone, deadzone);
+        controller.focus = Some(focus);
+
+        // look towards where the players aim
+        let look_ahead =
+            (aim_offset * controller.look_ahead).clamp_length_max(controller.max_look_ahead);
+
+        let mut target = focus + look_ahead;
+
+        if controller.clamp_to_level {
+            if let Some(rect) = level_rect_at(&rects, player_pos) {
+                target = clamp_to_rect(target, half_view, rect);
+            }
+        }
+
+        // exponential smoothing, independent of the frame rate
+        let t = 1.0 - (-controller.smoothing * time.delta_seconds()).exp();
+        let position = current.lerp(target, t);
+
+        controller.position = Some(position);
+        transform.translation = position.extend(1000.0);
     }
 }
 
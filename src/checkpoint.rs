@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::CollGroupsConfig;
+use crate::level_manager::{level_at, level_field};
+use crate::player::{player_system, Player};
+use crate::simulation::StepSystemAppExt;
+use crate::GameplayStage;
+
+/// Where a player comes back after dying
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RespawnPoint {
+    pub position: Vec2,
+    /// iid of the level the checkpoint is in
+    pub level_iid: Option<String>,
+}
+
+/// Sent when a dead player has been moved back to their respawn point
+pub struct PlayerRespawnEvent {
+    pub position: Vec2,
+    pub level_iid: Option<String>,
+}
+
+/// What happens to a level's enemies when the player respawns,
+/// set with the `EnemyReset` enum field of the level
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum EnemyResetPolicy {
+    /// killed enemies stay dead
+    Never,
+    /// the level is respawned with all its enemies
+    OnRespawn,
+}
+
+impl EnemyResetPolicy {
+    fn from_field(value: Option<String>) -> Self {
+        match value.as_deref() {
+            Some("OnRespawn") => EnemyResetPolicy::OnRespawn,
+            _ => EnemyResetPolicy::Never,
+        }
+    }
+}
+
+static CHECKPOINT_COLOR: Color = Color::rgba(0.3, 0.6, 1.0, 0.5);
+static CHECKPOINT_ACTIVE_COLOR: Color = Color::rgba(0.3, 1.0, 0.5, 0.7);
+
+#[derive(Component, Clone, Default)]
+pub struct Checkpoint;
+
+#[derive(Clone, Bundle)]
+pub struct CheckpointBundle {
+    checkpoint: Checkpoint,
+    #[bundle]
+    sprite_bundle: SpriteBundle,
+    collider: Collider,
+    sensor: Sensor,
+    coll_groups: CollisionGroups,
+    active_events: ActiveEvents,
+}
+
+impl CheckpointBundle {
+    pub fn new(size: Vec2) -> Self {
+        Self {
+            checkpoint: Checkpoint,
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: CHECKPOINT_COLOR,
+                    custom_size: Some(size),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            collider: Collider::cuboid(size.x / 2.0, size.y / 2.0),
+            sensor: Sensor,
+            coll_groups: CollGroupsConfig::trigger(),
+            active_events: ActiveEvents::COLLISION_EVENTS,
+        }
+    }
+}
+
+impl LdtkEntity for CheckpointBundle {
+    fn bundle_entity(
+        entity: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlas>,
+    ) -> CheckpointBundle {
+        CheckpointBundle::new(Vec2::new(entity.width as f32, entity.height as f32))
+    }
+}
+
+fn checkpoint_system(
+    checkpoint_query: Query<(&GlobalTransform, &Parent), With<Checkpoint>>,
+    mut player_query: Query<&mut Player>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut collision_events: EventReader<CollisionEvent>,
+) {
+    for collision in collision_events.iter() {
+        if let CollisionEvent::Started(e1, e2, _) = collision {
+            let (checkpoint_entity, player_entity) = match (
+                checkpoint_query.contains(*e1),
+                checkpoint_query.contains(*e2),
+            ) {
+                (true, _) => (*e1, *e2),
+                (_, true) => (*e2, *e1),
+                _ => continue,
+            };
+
+            let mut player = match player_query.get_mut(player_entity) {
+                Ok(player) => player,
+                Err(_) => continue,
+            };
+
+            let (transform, parent) = checkpoint_query.get(checkpoint_entity).unwrap();
+
+            // ldtk entities are children of their level
+            let level_iid = level_query
+                .get(parent.get())
+                .ok()
+                .and_then(|handle| levels.get(handle))
+                .map(|level| level.level.iid.clone());
+
+            let respawn = RespawnPoint {
+                position: transform.translation().truncate(),
+                level_iid,
+            };
+
+            if player.respawn_point() != Some(&respawn) {
+                println!("Checkpoint reached.");
+                player.set_respawn_point(Some(respawn));
+            }
+        }
+    }
+}
+
+// each player's last checkpoint is lit, also after its level has been respawned
+fn checkpoint_color_system(
+    mut checkpoint_query: Query<(&GlobalTransform, &mut Sprite), With<Checkpoint>>,
+    player_query: Query<&Player>,
+) {
+    for (transform, mut sprite) in checkpoint_query.iter_mut() {
+        let position = transform.translation().truncate();
+        let active = player_query.iter().any(|player| {
+            player
+                .respawn_point()
+                .is_some_and(|respawn| respawn.position == position)
+        });
+
+        let color = match active {
+            true => CHECKPOINT_ACTIVE_COLOR,
+            false => CHECKPOINT_COLOR,
+        };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+// resets the enemies of the level the player respawned in if it's set to do so,
+// and makes sure the checkpoint's level is spawned
+fn respawn_system(
+    mut commands: Commands,
+    mut respawn_events: EventReader<PlayerRespawnEvent>,
+    level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+    level_transform_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    mut level_set_query: Query<&mut LevelSet>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    for event in respawn_events.iter() {
+        // without a checkpoint the player is back at the start, in whichever level that is
+        let level_iid = match &event.level_iid {
+            Some(level_iid) => Some(level_iid.clone()),
+            None => level_at(&level_transform_query, &levels, event.position)
+                .map(|level| level.iid.clone()),
+        };
+
+        for (entity, handle) in level_query.iter() {
+            let level = match levels.get(handle) {
+                Some(level) => &level.level,
+                None => continue,
+            };
+
+            if Some(&level.iid) == level_iid.as_ref()
+                && EnemyResetPolicy::from_field(level_field(level, "EnemyReset"))
+                    == EnemyResetPolicy::OnRespawn
+            {
+                commands.entity(entity).insert(Respawn);
+            }
+        }
+
+        if let Some(level_iid) = &event.level_iid {
+            for mut level_set in level_set_query.iter_mut() {
+                if !level_set.iids.contains(level_iid) {
+                    level_set.iids.insert(level_iid.clone());
+                }
+            }
+        }
+    }
+}
+
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerRespawnEvent>()
+            // the respawn point is set before a player dying in the same step is moved to it
+            .add_step_system_to_stage(GameplayStage, checkpoint_system.before(player_system))
+            .add_system(checkpoint_color_system)
+            // in the step the event is sent, events live for two frames and steps can be further apart
+            .add_step_system_to_stage(GameplayStage, respawn_system.after(player_system));
+    }
+}
@@ -1,13 +1,23 @@
+use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 // game config
 pub static GAME_NAME: &str = "Astro bevy";
 pub static WINDOW_HEIGHT: f32 = 512.0;
 pub static WINDOW_WIDTH: f32 = 640.0;
 pub static CAMERA_SCALE: f32 = 1.5;
+// length of a gameplay and physics step, in seconds
+pub static FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+// internal resolution of pixel perfect rendering
+pub static INTERNAL_WIDTH: u32 = 320;
+pub static INTERNAL_HEIGHT: u32 = 256;
 
 // z-index config
 pub static Z_INDEX_BULLET: f32 = 5.0;
+// the player is at 10.0
+pub static Z_INDEX_GUN_FRONT: f32 = 20.0;
+pub static Z_INDEX_GUN_BEHIND: f32 = 9.0;
 // pub static Z_INDEX_ENEMY: f32 = 6.0;
 // pub static Z_INDEX_WALL: f32 = 1.0;
 
@@ -18,8 +28,8 @@ impl CollGroupsConfig {
     // pain...
 
     pub fn player() -> CollisionGroups {
-        // Group 0, interacts with groups 2, 3 and 4
-        CollisionGroups::new(0b00001, 0b11100)
+        // Group 0, interacts with groups 2, 3, 4, 5 and 6
+        CollisionGroups::new(0b00001, 0b1111100)
     }
 
     pub fn bullet_player() -> CollisionGroups {
@@ -34,19 +44,29 @@ impl CollGroupsConfig {
     }
 
     pub fn enemy() -> CollisionGroups {
-        // Group 3, interacts with group 0, 1 and 4
-        CollisionGroups::new(0b01000, 0b10011)
+        // Group 3, interacts with group 0, 1, 4 and 5
+        CollisionGroups::new(0b01000, 0b110011)
     }
 
     pub fn wall() -> CollisionGroups {
         // Group 4, interacts with group 0, 1, 2, 3
         CollisionGroups::new(0b10000, 0b01111)
     }
+
+    pub fn hazard() -> CollisionGroups {
+        // Group 5, interacts with group 0 and 3
+        CollisionGroups::new(0b100000, 0b01001)
+    }
+
+    pub fn trigger() -> CollisionGroups {
+        // Group 6, interacts with group 0
+        CollisionGroups::new(0b1000000, 0b00001)
+    }
 }
 
 // utility
 
-#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum SpriteDirection {
     #[default]
     Left,
@@ -54,3 +74,98 @@ pub enum SpriteDirection {
     Up,
     Down,
 }
+
+/// One of eight directions a character can face
+#[derive(Default, PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Facing {
+    Right,
+    UpRight,
+    Up,
+    UpLeft,
+    #[default]
+    Left,
+    DownLeft,
+    Down,
+    DownRight,
+}
+
+impl Facing {
+    /// Counter-clockwise, starting from the right
+    pub const ALL: [Facing; 8] = [
+        Facing::Right,
+        Facing::UpRight,
+        Facing::Up,
+        Facing::UpLeft,
+        Facing::Left,
+        Facing::DownLeft,
+        Facing::Down,
+        Facing::DownRight,
+    ];
+
+    /// Facing closest to the vector, `None` for a zero vector
+    pub fn from_vec2(vector: Vec2) -> Option<Self> {
+        if vector == Vec2::ZERO {
+            return None;
+        }
+
+        let octant = (vector.y.atan2(vector.x) / std::f32::consts::FRAC_PI_4).round() as i32;
+        Some(Self::ALL[octant.rem_euclid(8) as usize])
+    }
+
+    pub fn to_vec2(self) -> Vec2 {
+        let index = Self::ALL.iter().position(|f| *f == self).unwrap();
+        Vec2::from_angle(index as f32 * std::f32::consts::FRAC_PI_4)
+    }
+
+    /// Whether the character is looking away from the camera
+    pub fn is_up(self) -> bool {
+        matches!(self, Facing::UpRight | Facing::Up | Facing::UpLeft)
+    }
+
+    /// Closest of the four sprite directions, diagonals keep their horizontal side
+    pub fn sprite_direction(self) -> SpriteDirection {
+        match self {
+            Facing::Right | Facing::UpRight | Facing::DownRight => SpriteDirection::Right,
+            Facing::Left | Facing::UpLeft | Facing::DownLeft => SpriteDirection::Left,
+            Facing::Up => SpriteDirection::Up,
+            Facing::Down => SpriteDirection::Down,
+        }
+    }
+}
+
+/// What decides which way a character faces, players take it from the `--facing` setting
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum FacingMode {
+    /// towards where it's aiming
+    #[default]
+    Aim,
+    /// where it's moving, keeping the last facing when standing still
+    Movement,
+    /// a mix of both, `aim_weight` of 1.0 is the same as `Aim`
+    Blend { aim_weight: f32 },
+}
+
+impl FacingMode {
+    /// `aim`, `movement` or `blend`, which leans halfway
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "aim" => Some(FacingMode::Aim),
+            "movement" => Some(FacingMode::Movement),
+            "blend" => Some(FacingMode::Blend { aim_weight: 0.5 }),
+            _ => None,
+        }
+    }
+
+    pub fn facing(self, aim: Vec2, movement: Vec2, current: Facing) -> Facing {
+        let vector = match self {
+            FacingMode::Aim => aim,
+            FacingMode::Movement => movement,
+            FacingMode::Blend { aim_weight } => {
+                aim.normalize_or_zero() * aim_weight
+                    + movement.normalize_or_zero() * (1.0 - aim_weight)
+            }
+        };
+
+        Facing::from_vec2(vector).unwrap_or(current)
+    }
+}
@@ -0,0 +1,280 @@
+// headless determinism check, runs the same simulation twice and compares the results
+// run with `cargo run --release -- --check-determinism [steps] --seed [seed]`
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::bench::spawn_test_level;
+use crate::checkpoint::{CheckpointBundle, CheckpointPlugin};
+use crate::config::*;
+use crate::entity::bullet::*;
+use crate::entity::enemy::{Enemy, EnemyBundle, EnemyPlugin, EnemyState};
+use crate::entity::gun::{Gun, GunPlugin};
+use crate::feedback::{CameraKickEvent, FeedbackEvent};
+use crate::health::{Health, HealthPlugin};
+use crate::input_manager::{InputDevice, InputPlugin, InputSystem, LiveInput, PlayerInput};
+use crate::lava::{LavaBundle, LavaPlugin};
+use crate::movement::{MovementController, MovementPlugin};
+use crate::pixel_perfect::PixelPerfect;
+use crate::player::melee::Melee;
+use crate::player::shield::Shield;
+use crate::player::{Player, PlayerBundle, PlayerPlugin};
+use crate::quicksave::TimerState;
+use crate::simulation::{FixedTime, SimulationPlugin, StepSystemAppExt};
+use crate::status::{StatusPlugin, Statuses};
+use crate::GameplayStage;
+
+// scripted players shoot, swing, parry and dash this often, in steps
+const SHOT_INTERVAL: u64 = 10;
+const MELEE_INTERVAL: u64 = 25;
+const PARRY_INTERVAL: u64 = 35;
+const DASH_INTERVAL: u64 = 45;
+// and hold their shield up for the first steps of every this many
+const SHIELD_INTERVAL: u64 = 120;
+const SHIELD_STEPS: u64 = 30;
+const PLAYER_COUNT: usize = 2;
+
+fn spawn_enemies(mut commands: Commands, asset_server: Res<AssetServer>) {
+    for i in 0..8 {
+        let direction = match i % 2 {
+            0 => SpriteDirection::Right,
+            _ => SpriteDirection::Left,
+        };
+        let enemy = Enemy::from_state(&EnemyState {
+            direction,
+            last_shot: TimerState {
+                elapsed: 0.0,
+                duration: 0.5 + i as f32 * 0.1,
+            },
+        });
+
+        commands
+            .spawn_bundle(EnemyBundle::new(enemy, &asset_server))
+            .insert(Transform::from_xyz(
+                64.0 + i as f32 * 72.0,
+                64.0 + (i % 4) as f32 * 112.0,
+                0.0,
+            ));
+    }
+}
+
+fn spawn_players(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<Assets<TextureAtlas>>,
+) {
+    for id in 0..PLAYER_COUNT {
+        commands.spawn_bundle(PlayerBundle::new(
+            Player::new(id, 96.0 + id as f32 * 448.0, WINDOW_HEIGHT / 2.0),
+            InputDevice::Remote(id as u16),
+            &asset_server,
+            &mut textures,
+        ));
+    }
+}
+
+// lava across the middle of the room, where the players walk, and a checkpoint next to it
+fn spawn_hazards(mut commands: Commands) {
+    for i in 0..6 {
+        commands
+            .spawn_bundle(LavaBundle::new(32.0))
+            .insert_bundle(TransformBundle::from(Transform::from_xyz(
+                240.0 + i as f32 * 32.0,
+                WINDOW_HEIGHT / 2.0,
+                0.0,
+            )));
+    }
+
+    // checkpoints are children of their level
+    commands
+        .spawn_bundle(SpatialBundle::default())
+        .with_children(|level| {
+            level
+                .spawn_bundle(CheckpointBundle::new(Vec2::new(32.0, 32.0)))
+                .insert(Transform::from_xyz(
+                    WINDOW_WIDTH / 2.0,
+                    WINDOW_HEIGHT / 2.0 + 96.0,
+                    0.0,
+                ));
+        });
+}
+
+// players walking in circles around the room, aiming all over and using everything they have,
+// through the same live input remote players use
+fn scripted_players(
+    mut live: ResMut<LiveInput>,
+    player_query: Query<(&Player, &InputDevice)>,
+    fixed_time: Res<FixedTime>,
+) {
+    for (player, device) in player_query.iter() {
+        let step = fixed_time.steps + player.id as u64;
+        let angle = fixed_time.steps as f32 * 0.02 + player.id as f32 * 2.0;
+        let aim_angle = fixed_time.steps as f32 * 0.37 + player.id as f32;
+
+        live.merge(
+            *device,
+            PlayerInput {
+                movement: Vec2::new(angle.cos(), angle.sin()),
+                aim: Vec2::new(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0)
+                    + Vec2::from_angle(aim_angle) * 200.0,
+                dash: step.is_multiple_of(DASH_INTERVAL),
+                fire: step.is_multiple_of(SHOT_INTERVAL),
+                melee: step.is_multiple_of(MELEE_INTERVAL),
+                parry: step.is_multiple_of(PARRY_INTERVAL),
+                shield: step % SHIELD_INTERVAL < SHIELD_STEPS,
+            },
+        );
+    }
+}
+
+fn hash_vec2(vector: Vec2, hasher: &mut DefaultHasher) {
+    vector.x.to_bits().hash(hasher);
+    vector.y.to_bits().hash(hasher);
+}
+
+fn hash_controller(controller: &MovementController, hasher: &mut DefaultHasher) {
+    hash_vec2(controller.input, hasher);
+    controller.surface_friction.to_bits().hash(hasher);
+    controller.lock_remaining().to_bits().hash(hasher);
+}
+
+/// Hash of everything the simulation moves
+fn state_hash(app: &mut App) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    let mut players = app.world.query::<(
+        (&Player, &Transform, &Velocity, &MovementController),
+        (&Health, &Statuses, &Melee, &Shield),
+    )>();
+    for ((player, transform, velocity, controller), (health, statuses, melee, shield)) in
+        players.iter(&app.world)
+    {
+        hash_vec2(transform.translation.truncate(), &mut hasher);
+        hash_vec2(velocity.linvel, &mut hasher);
+        hash_controller(controller, &mut hasher);
+        format!("{:?}", player.state()).hash(&mut hasher);
+        format!("{:?}", health).hash(&mut hasher);
+        format!("{:?}", statuses.state()).hash(&mut hasher);
+        format!("{:?} {:?}", melee.state(), shield.state()).hash(&mut hasher);
+    }
+
+    let mut guns = app.world.query::<&Gun>();
+    for gun in guns.iter(&app.world) {
+        format!("{:?}", gun.state()).hash(&mut hasher);
+    }
+
+    let mut enemies = app.world.query::<(
+        (&Enemy, &Transform, &Velocity, &MovementController),
+        (&Health, &Statuses),
+    )>();
+    for ((enemy, transform, velocity, controller), (health, statuses)) in enemies.iter(&app.world) {
+        hash_vec2(transform.translation.truncate(), &mut hasher);
+        hash_vec2(velocity.linvel, &mut hasher);
+        hash_controller(controller, &mut hasher);
+        let last_shot = enemy.state().last_shot;
+        last_shot.elapsed.to_bits().hash(&mut hasher);
+        last_shot.duration.to_bits().hash(&mut hasher);
+        format!("{:?}", health).hash(&mut hasher);
+        format!("{:?}", statuses.state()).hash(&mut hasher);
+    }
+
+    let mut bullets = app
+        .world
+        .query_filtered::<(&Bullet, &Transform, &Velocity), Without<InactiveBullet>>();
+    for (bullet, transform, velocity) in bullets.iter(&app.world) {
+        hash_vec2(transform.translation.truncate(), &mut hasher);
+        hash_vec2(velocity.linvel, &mut hasher);
+        format!("{:?}", bullet.state()).hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+fn run(seed: u64, steps: usize) -> u64 {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(bevy::input::InputPlugin)
+        .add_event::<FeedbackEvent>()
+        .add_event::<CameraKickEvent>()
+        .add_asset::<Image>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<LdtkLevel>()
+        // no window, the keyboard, mouse and gamepads are read but never pressed
+        .init_resource::<Windows>()
+        .init_resource::<PixelPerfect>()
+        .insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            ..Default::default()
+        })
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        .add_plugin(SimulationPlugin {
+            seed,
+            headless: true,
+        })
+        // no renderer, so don't try to load textures
+        .insert_resource(BulletTextures {
+            player: Handle::default(),
+            enemy: Handle::default(),
+        })
+        .add_plugin(InputPlugin)
+        .add_plugin(PlayerPlugin)
+        .add_plugin(BulletPlugin)
+        .add_plugin(GunPlugin)
+        .add_plugin(EnemyPlugin)
+        .add_plugin(MovementPlugin)
+        .add_plugin(LavaPlugin)
+        .add_plugin(CheckpointPlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(StatusPlugin)
+        .add_startup_system(spawn_test_level)
+        .add_startup_system(spawn_hazards)
+        .add_startup_system(spawn_enemies)
+        .add_startup_system(spawn_players)
+        .add_step_system_to_stage(GameplayStage, scripted_players.before(InputSystem));
+
+    for _ in 0..steps {
+        app.update();
+    }
+
+    state_hash(&mut app)
+}
+
+/// Runs the simulation twice with the seed, returns whether both runs ended the same
+pub fn check_determinism(seed: u64, steps: usize) -> bool {
+    println!("determinism check: seed {}, {} steps", seed, steps);
+
+    let first = run(seed, steps);
+    let second = run(seed, steps);
+
+    println!("first run: {:016x}", first);
+    println!("second run: {:016x}", second);
+
+    match first == second {
+        true => println!("deterministic"),
+        false => println!("NOT deterministic"),
+    }
+
+    first == second
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_plays_out_the_same() {
+        assert!(check_determinism(7, 600));
+    }
+}
@@ -3,6 +3,7 @@ use bevy_rapier2d::prelude::*;
 
 pub mod bullet;
 pub mod enemy;
+pub mod gun;
 
 #[derive(Clone, Default, Bundle)]
 pub struct EntityBundle {
@@ -1,32 +1,55 @@
+use std::collections::BTreeSet;
+
+use bevy::ecs::query::WorldQuery;
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::config::*;
 use crate::entity::enemy::Enemy;
-use crate::player::Player;
+use crate::feedback::FeedbackEvent;
+use crate::health::{Health, HealthSystem};
+use crate::movement::{movement_system, Knockable, KnockableItem};
+use crate::player::shield::Shield;
+use crate::player::{player_system, Player};
+use crate::simulation::StepSystemAppExt;
+use crate::status::{StatusEffect, StatusEffectState, StatusSystem, Statuses};
+use crate::GameplayStage;
 
+#[allow(clippy::type_complexity)]
 fn bullet_system(
     mut commands: Commands,
 
-    mut bullet_query: Query<(Entity, &Transform, &Bullet)>,
-    enemy_query: Query<(Entity, &Enemy)>,
-    mut player_query: Query<&mut Player>,
+    mut bullet_query: Query<(Entity, FlyingBullet), Without<InactiveBullet>>,
+    mut enemy_query: Query<(&mut Health, &mut Statuses, Knockable), (With<Enemy>, Without<Player>)>,
+    mut player_query: Query<
+        (
+            &Transform,
+            &mut Health,
+            &mut Statuses,
+            Option<&Shield>,
+            Knockable,
+        ),
+        (With<Player>, Without<Bullet>),
+    >,
 
     mut bullets_collision: EventReader<CollisionEvent>,
+    (mut pool, textures): (ResMut<BulletPool>, Res<BulletTextures>),
+    mut feedback_events: EventWriter<FeedbackEvent>,
 ) {
-    // despawn bullet if off screen
-    for (entity, transform, _) in bullet_query.iter_mut() {
-        if transform.translation.x < -(2.0 * WINDOW_WIDTH) {
-            commands.entity(entity).despawn();
-        }
-        if transform.translation.x > 2.0 * WINDOW_WIDTH {
-            commands.entity(entity).despawn();
-        }
-        if transform.translation.y < -WINDOW_HEIGHT {
-            commands.entity(entity).despawn();
-        }
-        if transform.translation.y > WINDOW_HEIGHT {
-            commands.entity(entity).despawn();
+    // a bullet can be off screen and hit something in the same frame,
+    // so collect them first and return each one to the pool only once, in a stable order
+    let mut released = BTreeSet::new();
+
+    // release bullet if off screen
+    for (entity, flying) in bullet_query.iter() {
+        let transform = flying.transform;
+        if transform.translation.x < -(2.0 * WINDOW_WIDTH)
+            || transform.translation.x > 2.0 * WINDOW_WIDTH
+            || transform.translation.y < -WINDOW_HEIGHT
+            || transform.translation.y > WINDOW_HEIGHT
+        {
+            released.insert(entity);
         }
     }
 
@@ -36,38 +59,72 @@ fn bullet_system(
             let mut bullet_entity = *e1;
             let mut other_entity = *e2;
 
-            if let Ok((entity, _, _)) = bullet_query.get(*e1) {
+            if let Ok((entity, _)) = bullet_query.get(*e1) {
                 bullet_entity = entity;
                 other_entity = *e2;
             }
-            if let Ok((entity, _, _)) = bullet_query.get(*e2) {
+            if let Ok((entity, _)) = bullet_query.get(*e2) {
                 bullet_entity = entity;
                 other_entity = *e1;
             }
 
             // check if player bullet hit enemy
-            if let Ok((_, _, bullet)) = bullet_query.get(bullet_entity) {
-                if let BulletType::Player = bullet.type_ {
-                    if let Ok((_, _)) = enemy_query.get(other_entity) {
-                        // despawn enemy
-                        commands.entity(other_entity).despawn();
+            if let Ok((_, flying)) = bullet_query.get(bullet_entity) {
+                if let BulletType::Player = flying.bullet.type_ {
+                    if let Ok((mut health, mut statuses, mut knockable)) =
+                        enemy_query.get_mut(other_entity)
+                    {
+                        flying
+                            .bullet
+                            .hit(&mut health, &mut statuses, &mut knockable);
                     }
                 }
             }
 
             // check if enemy bullet hit player
-            if let Ok((_, _, bullet)) = bullet_query.get(bullet_entity) {
-                if let BulletType::Enemy = bullet.type_ {
-                    if let Ok(mut player) = player_query.get_mut(other_entity) {
-                        player.dead = true;
+            if let Ok((_, mut flying)) = bullet_query.get_mut(bullet_entity) {
+                if let BulletType::Enemy = flying.bullet.type_ {
+                    if let Ok((player_transform, mut health, mut statuses, shield, mut knockable)) =
+                        player_query.get_mut(other_entity)
+                    {
+                        let player_pos = player_transform.translation.truncate();
+                        let bullet_pos = flying.transform.translation.truncate();
+
+                        match shield {
+                            // a parried bullet keeps flying, back away from the player
+                            Some(shield) if shield.is_parrying() => {
+                                let direction = (bullet_pos - player_pos)
+                                    .try_normalize()
+                                    .unwrap_or(-flying.velocity.linvel.normalize_or_zero());
+                                flying.send_back(direction, shield.parry_speed, &textures);
+                                feedback_events.send(FeedbackEvent::Parried);
+                                continue;
+                            }
+                            // a blocked bullet still pushes
+                            Some(shield) if shield.blocks(player_pos, bullet_pos) => {
+                                knockable.push(flying.bullet.direction, flying.bullet.knockback);
+                            }
+                            _ => {
+                                flying
+                                    .bullet
+                                    .hit(&mut health, &mut statuses, &mut knockable);
+                                feedback_events.send(FeedbackEvent::PlayerHit);
+                            }
+                        }
                     }
                 }
             }
 
-            // despawn bullet
-            commands.entity(bullet_entity).despawn();
+            // release bullet
+            if bullet_query.contains(bullet_entity) {
+                released.insert(bullet_entity);
+            }
         }
     }
+
+    for entity in released {
+        pool.release(entity, &mut commands);
+    }
 }
 
 #[derive(Component, Clone)]
@@ -75,6 +132,13 @@ pub struct Bullet {
     speed: f32,
     texture: Handle<Image>,
     type_: BulletType,
+    /// where the bullet flies, set when it's shot
+    direction: Vec2,
+    damage: f32,
+    /// change in speed of what the bullet hits, in pixels per second
+    knockback: f32,
+    /// lingers on whatever the bullet hits
+    effect: Option<StatusEffect>,
 }
 
 impl Bullet {
@@ -83,6 +147,28 @@ impl Bullet {
             speed: 500.0,
             texture,
             type_: BulletType::Player,
+            direction: Vec2::X,
+            damage: 1.0,
+            knockback: 0.0,
+            effect: None,
+        }
+    }
+
+    pub fn with_knockback(mut self, knockback: f32) -> Self {
+        self.knockback = knockback;
+        self
+    }
+
+    pub fn with_effect(mut self, effect: Option<StatusEffect>) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    fn hit(&self, health: &mut Health, statuses: &mut Statuses, knockable: &mut KnockableItem) {
+        health.damage(self.damage);
+        knockable.push(self.direction, self.knockback);
+        if let Some(effect) = &self.effect {
+            statuses.apply(effect.clone());
         }
     }
 
@@ -91,15 +177,78 @@ impl Bullet {
         self
     }
 
-    pub fn spawn(&self, x: f32, y: f32, direction: Vec2, commands: &mut Commands) {
-        let coll_group = match self.type_ {
+    pub fn type_(&self) -> BulletType {
+        self.type_
+    }
+
+    pub fn state(&self) -> BulletState {
+        BulletState {
+            speed: self.speed,
+            type_: self.type_,
+            direction: self.direction,
+            damage: self.damage,
+            knockback: self.knockback,
+            effect: self.effect.as_ref().map(StatusEffect::state),
+        }
+    }
+
+    pub fn from_state(state: &BulletState, textures: &BulletTextures) -> Self {
+        Self {
+            speed: state.speed,
+            texture: textures.get(state.type_),
+            type_: state.type_,
+            direction: state.direction,
+            damage: state.damage,
+            knockback: state.knockback,
+            effect: state.effect.as_ref().map(StatusEffect::from_state),
+        }
+    }
+
+    fn coll_groups(&self) -> CollisionGroups {
+        match self.type_ {
             BulletType::Player => CollGroupsConfig::bullet_player(),
             BulletType::Enemy => CollGroupsConfig::bullet_enemy(),
+        }
+    }
+
+    pub fn spawn(
+        &self,
+        x: f32,
+        y: f32,
+        direction: Vec2,
+        pool: &mut BulletPool,
+        commands: &mut Commands,
+    ) -> Entity {
+        let coll_group = self.coll_groups();
+        let bullet = Bullet {
+            direction,
+            ..self.clone()
         };
 
         let velocity = direction * self.speed;
         // get rotation from vector
         let rotation = velocity.y.atan2(velocity.x);
+        let transform = Transform::from_xyz(x, y, Z_INDEX_BULLET)
+            .with_rotation(Quat::from_rotation_z(rotation));
+
+        // reuse a pooled bullet if there is one
+        if let Some(entity) = pool.take() {
+            pool.recycled += 1;
+
+            commands
+                .entity(entity)
+                .remove::<InactiveBullet>()
+                .insert(RigidBody::Dynamic)
+                .insert(Velocity::linear(velocity))
+                .insert(transform)
+                .insert(Visibility { is_visible: true })
+                .insert(self.texture.clone())
+                .insert(coll_group)
+                .insert(bullet);
+            return entity;
+        }
+
+        pool.spawned += 1;
 
         commands
             .spawn()
@@ -109,28 +258,220 @@ impl Bullet {
             .insert(GravityScale(0.0))
             .insert_bundle(SpriteBundle {
                 texture: self.texture.clone(),
-                transform: Transform::from_xyz(x, y, Z_INDEX_BULLET)
-                    .with_rotation(Quat::from_rotation_z(rotation)),
-
+                transform,
                 ..Default::default()
             })
             .insert(ActiveEvents::COLLISION_EVENTS)
             .insert(Ccd::enabled())
             .insert(coll_group)
-            .insert(self.clone());
+            .insert(bullet)
+            .id()
+    }
+}
+
+/// What's saved of a bullet in play, parried bullets are faster than new ones
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BulletState {
+    pub speed: f32,
+    pub type_: BulletType,
+    pub direction: Vec2,
+    pub damage: f32,
+    pub knockback: f32,
+    pub effect: Option<StatusEffectState>,
+}
+
+/// Parts of a bullet that change when it's sent back
+#[derive(WorldQuery)]
+#[world_query(mutable)]
+pub struct FlyingBullet<'w> {
+    pub bullet: &'w mut Bullet,
+    pub velocity: &'w mut Velocity,
+    pub transform: &'w mut Transform,
+    coll_groups: &'w mut CollisionGroups,
+    texture: &'w mut Handle<Image>,
+}
+
+impl<'w, 's> FlyingBulletItem<'w, 's> {
+    /// Hands the bullet to the other side, flying in the direction at `speed_factor` times its speed
+    pub fn send_back(&mut self, direction: Vec2, speed_factor: f32, textures: &BulletTextures) {
+        let type_ = match self.bullet.type_ {
+            BulletType::Player => BulletType::Enemy,
+            BulletType::Enemy => BulletType::Player,
+        };
+
+        self.bullet.type_ = type_;
+        self.bullet.texture = textures.get(type_);
+        self.bullet.speed *= speed_factor;
+        self.bullet.direction = direction;
+
+        *self.texture = self.bullet.texture.clone();
+        *self.coll_groups = self.bullet.coll_groups();
+        self.velocity.linvel = direction * self.bullet.speed;
+        self.transform.rotation = Quat::from_rotation_z(direction.y.atan2(direction.x));
+    }
+}
+
+/// Marks a pooled bullet that is currently not in play
+#[derive(Component, Clone, Copy, Default)]
+pub struct InactiveBullet;
+
+/// Recycles bullet entities instead of spawning and despawning them on every shot
+#[derive(Default)]
+pub struct BulletPool {
+    free: Vec<Entity>,
+    /// the same bullets as `free`, so a bullet released twice in a frame is only pooled once
+    pooled: BTreeSet<Entity>,
+    /// bullets spawned as new entities
+    pub spawned: usize,
+    /// bullets taken from the pool
+    pub recycled: usize,
+}
+
+impl BulletPool {
+    /// Takes the bullet out of play and keeps the entity for the next shot
+    pub fn release(&mut self, entity: Entity, commands: &mut Commands) {
+        // `InactiveBullet` only shows up once the commands are applied, so check the pool instead
+        if !self.pooled.insert(entity) {
+            return;
+        }
+
+        commands
+            .entity(entity)
+            .insert(InactiveBullet)
+            .insert(RigidBody::Fixed)
+            .insert(Velocity::zero())
+            .insert(CollisionGroups::new(0, 0))
+            .insert(Visibility { is_visible: false });
+
+        self.free.push(entity);
+    }
+
+    // a pooled bullet for the next shot, if there is one
+    fn take(&mut self) -> Option<Entity> {
+        let entity = self.free.pop()?;
+        self.pooled.remove(&entity);
+        Some(entity)
+    }
+
+    /// Number of bullets waiting in the pool
+    pub fn free_count(&self) -> usize {
+        self.free.len()
+    }
+}
+
+/// Bullet textures, loaded once instead of on every shot
+pub struct BulletTextures {
+    pub player: Handle<Image>,
+    pub enemy: Handle<Image>,
+}
+
+impl FromWorld for BulletTextures {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+
+        Self {
+            player: asset_server.load("bullet/player.png"),
+            enemy: asset_server.load("bullet/enemy.png"),
+        }
+    }
+}
+
+impl BulletTextures {
+    pub fn get(&self, type_: BulletType) -> Handle<Image> {
+        match type_ {
+            BulletType::Player => self.player.clone(),
+            BulletType::Enemy => self.enemy.clone(),
+        }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum BulletType {
     Player,
     Enemy,
 }
 
+/// Bullet system label, systems shooting from the pool run after it
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BulletSystem;
+
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(bullet_system);
+        app.init_resource::<BulletPool>()
+            .init_resource::<BulletTextures>()
+            .add_step_system_to_stage(
+                GameplayStage,
+                // hits land after players have moved and shot,
+                // and knockback takes control away before the controller steers
+                bullet_system
+                    .label(BulletSystem)
+                    .after(player_system)
+                    .before(StatusSystem)
+                    .before(HealthSystem)
+                    .before(movement_system),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+
+    fn shoot(world: &mut World, pool: &mut BulletPool) -> Entity {
+        let mut queue = CommandQueue::default();
+        let entity = Bullet::new(Handle::default()).spawn(
+            0.0,
+            0.0,
+            Vec2::X,
+            pool,
+            &mut Commands::new(&mut queue, world),
+        );
+        queue.apply(world);
+        entity
+    }
+
+    fn release(world: &mut World, pool: &mut BulletPool, entities: &[Entity]) {
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        for entity in entities {
+            pool.release(*entity, &mut commands);
+        }
+        queue.apply(world);
+    }
+
+    #[test]
+    fn released_bullets_are_shot_again() {
+        let mut world = World::new();
+        let mut pool = BulletPool::default();
+
+        let first = shoot(&mut world, &mut pool);
+        release(&mut world, &mut pool, &[first]);
+        assert_eq!(pool.free_count(), 1);
+        assert!(world.entity(first).contains::<InactiveBullet>());
+
+        let second = shoot(&mut world, &mut pool);
+        assert_eq!(second, first);
+        assert!(!world.entity(second).contains::<InactiveBullet>());
+        assert_eq!((pool.spawned, pool.recycled, pool.free_count()), (1, 1, 0));
+    }
+
+    #[test]
+    fn bullets_released_twice_are_pooled_once() {
+        let mut world = World::new();
+        let mut pool = BulletPool::default();
+
+        let bullet = shoot(&mut world, &mut pool);
+        // like a restart and a quickload clearing the bullets in the same frame
+        release(&mut world, &mut pool, &[bullet, bullet]);
+        assert_eq!(pool.free_count(), 1);
+
+        let first = shoot(&mut world, &mut pool);
+        let second = shoot(&mut world, &mut pool);
+        assert_ne!(first, second);
+        assert_eq!((pool.spawned, pool.recycled), (2, 1));
     }
 }
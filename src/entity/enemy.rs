@@ -4,12 +4,24 @@ use bevy::math::vec2;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
+use crate::animation::{Action, AnimationClip, AnimationState, Animations};
 use crate::config::*;
-use crate::entity::bullet::{Bullet, BulletType};
+use crate::entity::bullet::{Bullet, BulletPool, BulletSystem, BulletTextures, BulletType};
+use crate::health::{Health, HealthSystem};
+use crate::movement::{Knockback, MovementController};
+use crate::quicksave::TimerState;
+use crate::simulation::{FixedTime, GameRng, StepSystemAppExt};
+use crate::status::{StatusSystem, Statuses};
+use crate::GameplayStage;
 
 use super::EntitySpriteBundle;
 
+// how hard enemy bullets push players, as a change in speed in pixels per second
+static ENEMY_BULLET_KNOCKBACK: f32 = 120.0;
+
 #[derive(Default, Component, Clone)]
 pub struct Enemy {
     direction: SpriteDirection,
@@ -23,17 +35,41 @@ impl Enemy {
             last_shot: Timer::from_seconds(1.0, true),
         }
     }
+
+    pub fn state(&self) -> EnemyState {
+        EnemyState {
+            direction: self.direction,
+            last_shot: TimerState::from_timer(&self.last_shot),
+        }
+    }
+
+    pub fn from_state(state: &EnemyState) -> Self {
+        Enemy {
+            direction: state.direction,
+            last_shot: state.last_shot.to_timer(true),
+        }
+    }
+}
+
+/// Serialisable state of an enemy, for quicksaves
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnemyState {
+    pub direction: SpriteDirection,
+    pub last_shot: TimerState,
 }
 
 fn enemy_system(
-    mut enemy_query: Query<(&mut Enemy, &GlobalTransform)>,
+    mut enemy_query: Query<(&mut Enemy, &GlobalTransform, &Statuses)>,
 
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    time: Res<Time>,
+    bullet_textures: Res<BulletTextures>,
+    mut bullet_pool: ResMut<BulletPool>,
+    time: Res<FixedTime>,
+    mut rng: ResMut<GameRng>,
 ) {
-    for (mut enemy, transform) in enemy_query.iter_mut() {
-        enemy.last_shot.tick(time.delta());
+    for (mut enemy, transform, statuses) in enemy_query.iter_mut() {
+        // slowed enemies shoot less often, stunned ones not at all
+        enemy.last_shot.tick(time.delta().mul_f32(statuses.slow()));
 
         let bullet_direction = match enemy.direction {
             SpriteDirection::Right => vec2(1.0, 0.0),
@@ -42,12 +78,14 @@ fn enemy_system(
 
         if enemy.last_shot.finished() {
             // spawn bullet
-            Bullet::new(asset_server.load("bullet/enemy.png"))
+            Bullet::new(bullet_textures.enemy.clone())
                 .with_type(BulletType::Enemy)
+                .with_knockback(ENEMY_BULLET_KNOCKBACK)
                 .spawn(
                     transform.translation().x,
                     transform.translation().y - 4.0,
                     bullet_direction,
+                    &mut bullet_pool,
                     &mut commands,
                 );
 
@@ -55,7 +93,7 @@ fn enemy_system(
             enemy.last_shot.reset();
             enemy
                 .last_shot
-                .set_duration(Duration::from_secs_f32(rand::random::<f32>() * 2.0));
+                .set_duration(Duration::from_secs_f32(rng.gen::<f32>() * 2.0));
         }
     }
 }
@@ -65,6 +103,56 @@ pub struct EnemyBundle {
     #[bundle]
     entity_bundle: EntitySpriteBundle,
     enemy: Enemy,
+    health: Health,
+    statuses: Statuses,
+    velocity: Velocity,
+    controller: MovementController,
+    external_impulse: ExternalImpulse,
+    mass: ReadMassProperties,
+    knockback: Knockback,
+    animations: Animations,
+    animation_state: AnimationState,
+}
+
+// way an enemy faces, they only turn left or right
+fn enemy_facing(enemy: &Enemy) -> Facing {
+    match enemy.direction {
+        SpriteDirection::Left => Facing::Left,
+        _ => Facing::Right,
+    }
+}
+
+/// Clips of the enemy sprite, its images are the frames and flipping it turns it around
+fn enemy_animations(asset_server: &AssetServer, texture: Handle<Image>) -> Animations {
+    let mut animations = Animations::default().with_images(vec![
+        texture,
+        asset_server.load("enemy/snake_slither.png"),
+        asset_server.load("enemy/snake_hurt.png"),
+    ]);
+
+    for facing in [Facing::Left, Facing::Right] {
+        animations = animations
+            .with_clip(Action::Idle, facing, AnimationClip::new(vec![0, 1], 0.3))
+            .with_clip(
+                Action::Hurt,
+                facing,
+                AnimationClip::new(vec![2, 0, 2], 0.08).once(),
+            );
+    }
+
+    animations
+}
+
+// plays the hurt clip for a moment after a hit
+fn enemy_animation_system(mut enemy_query: Query<(&Enemy, &Health, &mut AnimationState)>) {
+    for (enemy, health, mut state) in enemy_query.iter_mut() {
+        let action = match health.is_hurt() {
+            true => Action::Hurt,
+            false => Action::Idle,
+        };
+
+        state.play(action, enemy_facing(enemy));
+    }
 }
 
 impl LdtkEntity for EnemyBundle {
@@ -77,7 +165,6 @@ impl LdtkEntity for EnemyBundle {
         _: &mut Assets<TextureAtlas>,
     ) -> EnemyBundle {
         let mut enemy = Enemy::new();
-        let mut x_flip = false;
 
         if let Some(rotation_field) = entity
             .field_instances
@@ -86,16 +173,19 @@ impl LdtkEntity for EnemyBundle {
         {
             if let FieldValue::Enum(Some(rot)) = &rotation_field.value {
                 match rot.as_str() {
-                    "Left" => {
-                        enemy.direction = SpriteDirection::Left;
-                        x_flip = true
-                    }
+                    "Left" => enemy.direction = SpriteDirection::Left,
                     "Right" => enemy.direction = SpriteDirection::Right,
                     _ => {}
                 }
             }
         }
 
+        EnemyBundle::new(enemy, asset_server)
+    }
+}
+
+impl EnemyBundle {
+    pub fn new(enemy: Enemy, asset_server: &AssetServer) -> Self {
         // enemy collider
         let collider = Collider::compound(vec![(
             Vec2::new(-2.0, -2.5),
@@ -103,12 +193,15 @@ impl LdtkEntity for EnemyBundle {
             Collider::cuboid(11.0, 13.0),
         )]);
 
+        let texture = asset_server.load("enemy/snake.png");
+        let animation_state = AnimationState::new(Action::Idle, enemy_facing(&enemy));
+
         EnemyBundle {
             entity_bundle: EntitySpriteBundle {
                 sprite_bundle: SpriteBundle {
-                    texture: asset_server.load("enemy/snake.png"),
+                    texture: texture.clone(),
                     sprite: Sprite {
-                        flip_x: x_flip,
+                        flip_x: enemy.direction == SpriteDirection::Left,
                         ..Default::default()
                     },
                     ..Default::default()
@@ -120,6 +213,16 @@ impl LdtkEntity for EnemyBundle {
                 locked_axes: LockedAxes::ROTATION_LOCKED,
             },
             enemy,
+            health: Health::default(),
+            statuses: Statuses::default(),
+            velocity: Velocity::zero(),
+            // enemies stand still, the controller only stops them after they're knocked back
+            controller: MovementController::new(0.0, 0.0, 1200.0),
+            external_impulse: ExternalImpulse::default(),
+            mass: ReadMassProperties::default(),
+            knockback: Knockback::default(),
+            animations: enemy_animations(asset_server, texture),
+            animation_state,
         }
     }
 }
@@ -128,6 +231,13 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(enemy_system);
+        app.add_step_system_to_stage(
+            GameplayStage,
+            enemy_system
+                .after(BulletSystem)
+                .after(StatusSystem)
+                .before(HealthSystem),
+        )
+        .add_step_system_to_stage(GameplayStage, enemy_animation_system.after(HealthSystem));
     }
 }
@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::*;
+use crate::simulation::{FixedTime, StepSystemAppExt};
+use crate::GameplayStage;
+
+/// How a weapon feels to shoot
+#[derive(Clone, Copy, Debug)]
+pub struct GunHandling {
+    /// how much each shot pushes the owner back, as a change in speed in pixels per second
+    pub recoil: f32,
+    /// how far the gun slides back on each shot, in pixels
+    pub kick: f32,
+    /// how fast the gun slides back into place, higher is quicker
+    pub kick_recovery: f32,
+    /// how far each shot pushes the camera, in pixels
+    pub camera_kick: f32,
+    /// spread of a single shot, in radians either side of the aim
+    pub min_spread: f32,
+    /// widest the spread gets under sustained fire
+    pub max_spread: f32,
+    /// spread added by each shot
+    pub spread_per_shot: f32,
+    /// spread recovered per second, also between shots, so sustained fire only widens
+    /// the spread when `spread_per_shot` outpaces it
+    pub spread_recovery: f32,
+    /// how hard the bullets push what they hit, as a change in speed in pixels per second
+    pub knockback: f32,
+}
+
+impl Default for GunHandling {
+    // perfectly accurate and still
+    fn default() -> Self {
+        Self {
+            recoil: 0.0,
+            kick: 0.0,
+            kick_recovery: 20.0,
+            camera_kick: 0.0,
+            min_spread: 0.0,
+            max_spread: 0.0,
+            spread_per_shot: 0.0,
+            spread_recovery: 0.0,
+            knockback: 0.0,
+        }
+    }
+}
+
+/// A gun held by its parent entity, pointing at `aim`
+#[derive(Component, Clone)]
+pub struct Gun {
+    texture: Handle<Image>,
+    /// where the gun is held, from the centre of its owner
+    pub grip: Vec2,
+    /// point of the sprite the gun turns around, from the centre of the sprite
+    pub pivot: Vec2,
+    /// end of the barrel, from the centre of the sprite when aiming right
+    pub muzzle: Vec2,
+    /// world position the gun points at, set by whoever holds it
+    pub aim: Vec2,
+    pub handling: GunHandling,
+    /// current spread, grows while shooting
+    spread: f32,
+    /// how far the gun is slid back
+    kick_offset: f32,
+}
+
+impl Gun {
+    pub fn new(texture: Handle<Image>) -> Self {
+        Self {
+            texture,
+            grip: Vec2::ZERO,
+            pivot: Vec2::ZERO,
+            muzzle: Vec2::ZERO,
+            aim: Vec2::ZERO,
+            handling: GunHandling::default(),
+            spread: 0.0,
+            kick_offset: 0.0,
+        }
+    }
+
+    pub fn state(&self) -> GunState {
+        GunState {
+            aim: self.aim,
+            spread: self.spread,
+            kick_offset: self.kick_offset,
+        }
+    }
+
+    pub fn restore(&mut self, state: &GunState) {
+        self.aim = state.aim;
+        self.spread = state.spread;
+        self.kick_offset = state.kick_offset;
+    }
+
+    pub fn with_handling(mut self, handling: GunHandling) -> Self {
+        self.spread = handling.min_spread;
+        self.handling = handling;
+        self
+    }
+
+    pub fn with_grip(mut self, grip: Vec2) -> Self {
+        self.grip = grip;
+        self
+    }
+
+    pub fn with_pivot(mut self, pivot: Vec2) -> Self {
+        self.pivot = pivot;
+        self
+    }
+
+    pub fn with_muzzle(mut self, muzzle: Vec2) -> Self {
+        self.muzzle = muzzle;
+        self
+    }
+
+    /// Spawns the gun in the owner's hands
+    pub fn spawn(&self, owner: Entity, commands: &mut Commands) -> Entity {
+        let gun = commands
+            .spawn_bundle(SpriteBundle {
+                texture: self.texture.clone(),
+                transform: Transform::from_translation(self.grip.extend(0.0)),
+                ..Default::default()
+            })
+            .insert(self.clone())
+            .id();
+
+        commands.entity(owner).add_child(gun);
+        gun
+    }
+
+    // rotation and vertical flip of the gun held by an owner at this position,
+    // flipped when aiming left so it isn't upside down
+    fn pose(&self, owner_position: Vec2) -> (Quat, Vec2) {
+        let direction = self.aim - (owner_position + self.grip);
+        let rotation = Quat::from_rotation_z(direction.y.atan2(direction.x));
+        let scale = match direction.x < 0.0 {
+            true => Vec2::new(1.0, -1.0),
+            false => Vec2::ONE,
+        };
+
+        (rotation, scale)
+    }
+
+    /// World position of the end of the barrel, for an owner at this position
+    pub fn muzzle_position(&self, owner_position: Vec2) -> Vec2 {
+        let (rotation, scale) = self.pose(owner_position);
+        let muzzle = rotation * ((self.muzzle - self.pivot) * scale).extend(0.0);
+
+        owner_position + self.grip + muzzle.truncate()
+    }
+
+    /// Where a shot leaves the gun: the muzzle, or the first wall between the owner and it,
+    /// so a gun poking through a wall can't shoot into the next room
+    pub fn shot_origin(&self, owner_position: Vec2, rapier: &RapierContext) -> Vec2 {
+        let muzzle = self.muzzle_position(owner_position);
+        let ray = muzzle - owner_position;
+
+        // only walls stop the barrel
+        let walls = CollisionGroups::new(
+            CollGroupsConfig::bullet_player().memberships,
+            CollGroupsConfig::wall().memberships,
+        );
+
+        // the ray is as long as the distance to the muzzle, so the time of impact is a fraction of it
+        match rapier.cast_ray(
+            owner_position,
+            ray,
+            1.0,
+            true,
+            QueryFilter::new().groups(walls.into()),
+        ) {
+            Some((_, toi)) => owner_position + ray * toi,
+            None => muzzle,
+        }
+    }
+
+    /// Direction of a shot fired now, somewhere within the spread. Widens the spread and kicks the gun back
+    pub fn fire(&mut self, owner_position: Vec2, rng: &mut impl Rng) -> Vec2 {
+        let angle = (rng.gen::<f32>() * 2.0 - 1.0) * self.spread;
+        let direction = Vec2::from_angle(angle).rotate(self.direction(owner_position));
+
+        self.spread = (self.spread + self.handling.spread_per_shot).min(self.handling.max_spread);
+        self.kick_offset = self.handling.kick;
+
+        direction
+    }
+
+    // settles the spread and slides the gun back into place
+    fn recover(&mut self, delta: f32) {
+        self.spread =
+            (self.spread - self.handling.spread_recovery * delta).max(self.handling.min_spread);
+        self.kick_offset *= (-self.handling.kick_recovery * delta).exp();
+    }
+
+    /// Direction from the pivot to the aim
+    pub fn direction(&self, owner_position: Vec2) -> Vec2 {
+        (self.aim - (owner_position + self.grip)).normalize_or_zero()
+    }
+}
+
+/// What's saved of a gun, the rest are its settings and handling
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GunState {
+    pub aim: Vec2,
+    pub spread: f32,
+    pub kick_offset: f32,
+}
+
+// turns guns around their pivot towards what they aim at
+pub fn gun_system(
+    mut gun_query: Query<(&mut Gun, &Parent, &mut Transform)>,
+    owner_query: Query<&GlobalTransform>,
+    time: Res<FixedTime>,
+) {
+    for (mut gun, parent, mut transform) in gun_query.iter_mut() {
+        gun.recover(time.delta_seconds());
+
+        let owner = match owner_query.get(parent.get()) {
+            Ok(owner) => owner.translation(),
+            Err(_) => continue,
+        };
+
+        let (rotation, scale) = gun.pose(owner.truncate());
+
+        // the pivot stays on the grip whichever way the gun points
+        let pivot = rotation * (gun.pivot * scale).extend(0.0);
+        let kick = rotation * Vec3::X * gun.kick_offset;
+        let position = gun.grip - pivot.truncate() - kick.truncate();
+
+        // held behind the owner when aiming away from the camera
+        let z = match Facing::from_vec2(gun.direction(owner.truncate())).is_some_and(Facing::is_up)
+        {
+            true => Z_INDEX_GUN_BEHIND,
+            false => Z_INDEX_GUN_FRONT,
+        };
+
+        transform.translation = position.extend(z - owner.z);
+        transform.rotation = rotation;
+        transform.scale = scale.extend(1.0);
+    }
+}
+
+/// Gun system label, systems aiming guns run before it
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GunSystem;
+
+pub struct GunPlugin;
+
+impl Plugin for GunPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_step_system_to_stage(GameplayStage, gun_system.label(GunSystem));
+    }
+}
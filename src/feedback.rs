@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+
+use crate::camera::camera_system;
+use crate::pixel_perfect::PixelPerfect;
+
+/// Gameplay moments that shake the screen and freeze the game for a moment
+#[derive(Clone, Copy, Debug)]
+pub enum FeedbackEvent {
+    PlayerShot,
+    PlayerHit,
+    EnemyKilled,
+    Parried,
+}
+
+/// Pushes the camera by an offset that settles back, like the recoil of a shot
+pub struct CameraKickEvent(pub Vec2);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Feedback {
+    /// added to the camera's trauma, which is capped at 1.0
+    pub trauma: f32,
+    /// how long gameplay freezes, in seconds
+    pub hit_stop: f32,
+}
+
+/// How strong the feedback for each event is
+#[derive(Clone, Debug)]
+pub struct FeedbackSettings {
+    pub player_shot: Feedback,
+    pub player_hit: Feedback,
+    pub enemy_killed: Feedback,
+    pub parried: Feedback,
+}
+
+impl FeedbackSettings {
+    fn get(&self, event: FeedbackEvent) -> Feedback {
+        match event {
+            FeedbackEvent::PlayerShot => self.player_shot,
+            FeedbackEvent::PlayerHit => self.player_hit,
+            FeedbackEvent::EnemyKilled => self.enemy_killed,
+            FeedbackEvent::Parried => self.parried,
+        }
+    }
+}
+
+impl Default for FeedbackSettings {
+    fn default() -> Self {
+        Self {
+            player_shot: Feedback {
+                trauma: 0.15,
+                hit_stop: 0.0,
+            },
+            player_hit: Feedback {
+                trauma: 0.6,
+                hit_stop: 0.12,
+            },
+            enemy_killed: Feedback {
+                trauma: 0.3,
+                hit_stop: 0.05,
+            },
+            parried: Feedback {
+                trauma: 0.2,
+                hit_stop: 0.08,
+            },
+        }
+    }
+}
+
+/// Trauma based camera shake, added on top of where the camera controller puts the camera
+#[derive(Component, Clone, Debug)]
+pub struct ScreenShake {
+    /// 0.0 to 1.0, the shake grows with its square
+    pub trauma: f32,
+    /// how much trauma goes away per second
+    pub decay: f32,
+    /// offset at full trauma, in pixels
+    pub max_offset: f32,
+    /// rotation at full trauma, in radians
+    pub max_angle: f32,
+    /// how fast the shake moves
+    pub frequency: f32,
+    /// offset from kicks, in pixels
+    pub kick: Vec2,
+    /// how fast kicks settle, higher is quicker
+    pub kick_recovery: f32,
+}
+
+impl ScreenShake {
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.0);
+    }
+}
+
+impl Default for ScreenShake {
+    fn default() -> Self {
+        Self {
+            trauma: 0.0,
+            decay: 1.5,
+            max_offset: 12.0,
+            max_angle: 0.05,
+            frequency: 25.0,
+            kick: Vec2::ZERO,
+            kick_recovery: 12.0,
+        }
+    }
+}
+
+/// Time left until gameplay continues
+#[derive(Default)]
+pub struct HitStop {
+    remaining: f32,
+}
+
+impl HitStop {
+    pub fn is_active(&self) -> bool {
+        self.remaining > 0.0
+    }
+}
+
+fn feedback_system(
+    mut feedback_events: EventReader<FeedbackEvent>,
+    settings: Res<FeedbackSettings>,
+    mut hit_stop: ResMut<HitStop>,
+    mut shake_query: Query<&mut ScreenShake>,
+    mut kick_events: EventReader<CameraKickEvent>,
+) {
+    for CameraKickEvent(kick) in kick_events.iter() {
+        for mut shake in shake_query.iter_mut() {
+            shake.kick += *kick;
+        }
+    }
+
+    for event in feedback_events.iter() {
+        let feedback = settings.get(*event);
+
+        for mut shake in shake_query.iter_mut() {
+            shake.add_trauma(feedback.trauma);
+        }
+
+        // overlapping hit-stops don't add up
+        hit_stop.remaining = hit_stop.remaining.max(feedback.hit_stop);
+    }
+}
+
+// the fixed timestep holds gameplay and physics while it's active
+fn hit_stop_system(mut hit_stop: ResMut<HitStop>, time: Res<Time>) {
+    if hit_stop.is_active() {
+        hit_stop.remaining -= time.delta_seconds();
+    }
+}
+
+// smooth noise in -1.0..1.0, so the shake doesn't jitter every frame
+fn noise(t: f32, seed: f32) -> f32 {
+    ((t + seed).sin() + (2.3 * t + seed * 1.7).sin() * 0.5) / 1.5
+}
+
+fn screen_shake_system(
+    mut shake_query: Query<(&mut ScreenShake, &mut Transform)>,
+    time: Res<Time>,
+    pixel_perfect: Res<PixelPerfect>,
+) {
+    for (mut shake, mut transform) in shake_query.iter_mut() {
+        shake.trauma = (shake.trauma - shake.decay * time.delta_seconds()).max(0.0);
+
+        let amount = shake.trauma * shake.trauma;
+        let t = time.seconds_since_startup() as f32 * shake.frequency;
+
+        transform.translation.x += shake.max_offset * amount * noise(t, 0.0);
+        transform.translation.y += shake.max_offset * amount * noise(t, 10.0);
+
+        let settle = (-shake.kick_recovery * time.delta_seconds()).exp();
+        shake.kick *= settle;
+        transform.translation += shake.kick.extend(0.0);
+        // a rotated low resolution image breaks up the pixel grid
+        let angle = match pixel_perfect.enabled {
+            true => 0.0,
+            false => shake.max_angle * amount * noise(t, 20.0),
+        };
+        transform.rotation = Quat::from_rotation_z(angle);
+    }
+}
+
+pub struct FeedbackPlugin;
+
+impl Plugin for FeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FeedbackEvent>()
+            .add_event::<CameraKickEvent>()
+            .init_resource::<FeedbackSettings>()
+            .init_resource::<HitStop>()
+            .add_system(feedback_system.before(hit_stop_system))
+            .add_system(hit_stop_system)
+            .add_system(screen_shake_system.after(camera_system));
+    }
+}
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::entity::enemy::Enemy;
+use crate::feedback::FeedbackEvent;
+use crate::player::Player;
+use crate::simulation::{FixedTime, StepSystemAppExt};
+use crate::GameplayStage;
+
+// how long an entity reacts to a hit, in seconds
+static HURT_TIME: f32 = 0.2;
+
+/// What an entity can take before it dies
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    /// seconds left of the reaction to the last hit
+    hurt: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            hurt: 0.0,
+        }
+    }
+
+    pub fn damage(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+        if amount > 0.0 {
+            self.hurt = HURT_TIME;
+        }
+    }
+
+    /// Whether the entity was hit a moment ago
+    pub fn is_hurt(&self) -> bool {
+        self.hurt > 0.0
+    }
+
+    pub fn heal_full(&mut self) {
+        self.current = self.max;
+        self.hurt = 0.0;
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+// players out of health respawn, enemies out of health die
+fn health_system(
+    mut commands: Commands,
+    mut health_query: Query<(Entity, &mut Health, Option<&mut Player>, Option<&Enemy>)>,
+    mut feedback_events: EventWriter<FeedbackEvent>,
+    time: Res<FixedTime>,
+) {
+    for (entity, mut health, player, enemy) in health_query.iter_mut() {
+        if health.is_hurt() {
+            health.hurt = (health.hurt - time.delta_seconds()).max(0.0);
+        }
+
+        if !health.is_dead() {
+            continue;
+        }
+
+        // players get their health back when they respawn
+        if let Some(mut player) = player {
+            player.dead = true;
+        } else if enemy.is_some() {
+            commands.entity(entity).despawn_recursive();
+            feedback_events.send(FeedbackEvent::EnemyKilled);
+        }
+    }
+}
+
+/// Health system label, systems dealing damage run before it
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HealthSystem;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_step_system_to_stage(GameplayStage, health_system.label(HealthSystem));
+    }
+}
@@ -1,17 +1,231 @@
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::camera::CameraController;
+use crate::pixel_perfect::PixelPerfect;
+use crate::simulation::StepSystemAppExt;
+use crate::GameplayStage;
+
+/// What a player does in a gameplay step, from their input device or from a replay
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerInput {
+    /// direction to move in, at most 1.0 long
+    pub movement: Vec2,
+    /// world position the player aims at
+    pub aim: Vec2,
+    pub dash: bool,
+    pub fire: bool,
+    pub melee: bool,
+    pub parry: bool,
+    /// held, unlike the other buttons which are presses
+    pub shield: bool,
+}
+
+/// Systems that fill in `PlayerInput`, gameplay reading it runs after them
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputSystem;
+
+/// Where a player's input comes from
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InputDevice {
+    #[default]
+    KeyboardMouse,
+    Gamepad(Gamepad),
+    /// a client over the network, by its number on the host
+    Remote(u16),
+}
+
+// how far from the player a gamepad aims, in pixels
+static GAMEPAD_AIM_DISTANCE: f32 = 64.0;
+// stick deflection below this is ignored
+static GAMEPAD_DEADZONE: f32 = 0.2;
+
+/// Input of every device in the frames since the last gameplay step,
+/// so presses aren't lost on frames without a step
+#[derive(Default)]
+pub struct LiveInput {
+    inputs: HashMap<InputDevice, PlayerInput>,
+    /// gamepads aim in a direction from the player, kept when the stick is let go
+    aim_directions: HashMap<Gamepad, Vec2>,
+}
+
+impl LiveInput {
+    /// Input of a device the game doesn't read itself, presses count until a step uses them
+    pub fn merge(&mut self, device: InputDevice, input: PlayerInput) {
+        let live = self.inputs.entry(device).or_default();
+        live.movement = input.movement;
+        live.aim = input.aim;
+        live.dash |= input.dash;
+        live.fire |= input.fire;
+        live.melee |= input.melee;
+        live.parry |= input.parry;
+        live.shield = input.shield;
+    }
+
+    pub fn remove(&mut self, device: InputDevice) {
+        self.inputs.remove(&device);
+    }
+}
+
+fn stick(
+    axes: &Axis<GamepadAxis>,
+    gamepad: Gamepad,
+    x: GamepadAxisType,
+    y: GamepadAxisType,
+) -> Vec2 {
+    let x = axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0);
+    let y = axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0);
+    Vec2::new(x, y)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_live_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    (gamepads, gamepad_buttons, gamepad_axes): (
+        Res<Gamepads>,
+        Res<Input<GamepadButton>>,
+        Res<Axis<GamepadAxis>>,
+    ),
+    windows: Res<Windows>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<CameraController>>,
+    pixel_perfect: Res<PixelPerfect>,
+    player_query: Query<(&InputDevice, &GlobalTransform)>,
+    mut live: ResMut<LiveInput>,
+) {
+    let live = &mut *live;
+
+    // keyboard and mouse
+    let up = keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up);
+    let down = keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down);
+    let left = keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left);
+    let right = keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right);
+
+    let x_axis = -(left as i8) + right as i8;
+    let y_axis = -(down as i8) + up as i8;
+
+    let input = live.inputs.entry(InputDevice::KeyboardMouse).or_default();
+    input.movement = Vec2::new(x_axis as f32, y_axis as f32).normalize_or_zero();
+
+    if let (Some(window), Ok((camera, camera_transform))) =
+        (windows.get_primary(), q_camera.get_single())
+    {
+        input.aim = get_mouse_world_pos(
+            window,
+            camera,
+            camera_transform,
+            pixel_perfect.screen_rect(),
+        );
+    }
+
+    // presses count until a step uses them
+    input.dash |= keyboard_input.just_pressed(KeyCode::Space);
+    input.fire |= buttons.just_pressed(MouseButton::Left);
+    input.melee |= buttons.just_pressed(MouseButton::Right);
+    input.parry |= keyboard_input.just_pressed(KeyCode::F);
+    input.shield = keyboard_input.pressed(KeyCode::LShift);
+
+    // a gamepad that's gone doesn't keep its last input
+    live.inputs.retain(|device, _| match device {
+        InputDevice::Gamepad(gamepad) => gamepads.contains(gamepad),
+        _ => true,
+    });
+
+    // gamepads, left stick moves and right stick aims
+    for gamepad in gamepads.iter() {
+        let device = InputDevice::Gamepad(*gamepad);
+        let input = live.inputs.entry(device).or_default();
+
+        let movement = stick(
+            &gamepad_axes,
+            *gamepad,
+            GamepadAxisType::LeftStickX,
+            GamepadAxisType::LeftStickY,
+        );
+        input.movement = match movement.length() < GAMEPAD_DEADZONE {
+            true => Vec2::ZERO,
+            false => movement.clamp_length_max(1.0),
+        };
+
+        let aim = stick(
+            &gamepad_axes,
+            *gamepad,
+            GamepadAxisType::RightStickX,
+            GamepadAxisType::RightStickY,
+        );
+        let direction = live.aim_directions.entry(*gamepad).or_insert(Vec2::X);
+        if aim.length() >= GAMEPAD_DEADZONE {
+            *direction = aim.normalize();
+        }
+
+        if let Some((_, transform)) = player_query.iter().find(|(d, _)| **d == device) {
+            input.aim = transform.translation().truncate() + *direction * GAMEPAD_AIM_DISTANCE;
+        }
+
+        let pressed =
+            |button_type| gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, button_type));
+        input.dash |= pressed(GamepadButtonType::South) || pressed(GamepadButtonType::LeftTrigger);
+        input.fire |=
+            pressed(GamepadButtonType::RightTrigger) || pressed(GamepadButtonType::RightTrigger2);
+        input.melee |= pressed(GamepadButtonType::West);
+        input.parry |= pressed(GamepadButtonType::East);
+        input.shield = gamepad_buttons.pressed(GamepadButton::new(
+            *gamepad,
+            GamepadButtonType::LeftTrigger2,
+        ));
+    }
+}
+
+// hands each player the live input of their device for this step
+pub fn input_system(
+    mut live: ResMut<LiveInput>,
+    mut input_query: Query<(&InputDevice, &mut PlayerInput)>,
+) {
+    for (device, mut input) in input_query.iter_mut() {
+        // a disconnected gamepad leaves its player standing still
+        *input = live.inputs.get(device).copied().unwrap_or_default();
+    }
+
+    for input in live.inputs.values_mut() {
+        input.dash = false;
+        input.fire = false;
+        input.melee = false;
+        input.parry = false;
+    }
+}
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LiveInput>()
+            .add_system_to_stage(CoreStage::PreUpdate, read_live_input_system)
+            .add_step_system_to_stage(GameplayStage, input_system.label(InputSystem));
+    }
+}
 
 pub fn get_mouse_world_pos(
     window: &Window,
     camera: &Camera,
     camera_transform: &GlobalTransform,
+    screen_rect: Option<(Vec2, Vec2)>,
 ) -> Vec2 {
     if let Some(mouse_pos) = window.cursor_position() {
         // get mouse position in world space
-        // get the size of the window
-        let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+        // get the part of the window the game is shown in, without the letterbox
+        let (viewport_min, viewport_max) = screen_rect
+            .or_else(|| camera.logical_viewport_rect())
+            .unwrap_or((Vec2::ZERO, Vec2::new(window.width(), window.height())));
+        let viewport_size = viewport_max - viewport_min;
+
+        // the viewport is measured from the top left corner, the cursor from the bottom left
+        let viewport_pos = Vec2::new(
+            mouse_pos.x - viewport_min.x,
+            mouse_pos.y - (window.height() - viewport_max.y),
+        );
 
         // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
-        let ndc = (mouse_pos / window_size) * 2.0 - Vec2::ONE;
+        let ndc = (viewport_pos / viewport_size) * 2.0 - Vec2::ONE;
 
         // matrix for undoing the projection and camera transform
         let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
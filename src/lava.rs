@@ -0,0 +1,93 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::config::CollGroupsConfig;
+use crate::entity::bullet::BulletSystem;
+use crate::feedback::FeedbackEvent;
+use crate::health::HealthSystem;
+use crate::movement::Surface;
+use crate::player::Player;
+use crate::simulation::StepSystemAppExt;
+use crate::status::{StatusEffect, StatusKind, StatusSystem, Statuses};
+use crate::GameplayStage;
+
+// lava is thick, so entities get up to speed and stop slower on it
+pub static LAVA_FRICTION: f32 = 0.5;
+
+fn lava_system(
+    lava_query: Query<(), With<Lava>>,
+    mut player_query: Query<&mut Player>,
+    mut status_query: Query<&mut Statuses, Without<Player>>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut feedback_events: EventWriter<FeedbackEvent>,
+) {
+    for collision in collision_events.iter() {
+        if let CollisionEvent::Started(e1, e2, _) = collision {
+            let other_entity = match (lava_query.contains(*e1), lava_query.contains(*e2)) {
+                (true, _) => *e2,
+                (_, true) => *e1,
+                _ => continue,
+            };
+
+            // kill player standing in lava
+            if let Ok(mut player) = player_query.get_mut(other_entity) {
+                player.dead = true;
+                feedback_events.send(FeedbackEvent::PlayerHit);
+            }
+
+            // everything else burns
+            if let Ok(mut statuses) = status_query.get_mut(other_entity) {
+                statuses.apply(StatusEffect::of(StatusKind::Burn));
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Lava;
+
+#[derive(Clone, Debug, Bundle)]
+pub struct LavaBundle {
+    lava: Lava,
+    surface: Surface,
+    collider: Collider,
+    sensor: Sensor,
+    coll_groups: CollisionGroups,
+    active_events: ActiveEvents,
+}
+
+impl LavaBundle {
+    /// A lava tile `size` pixels wide
+    pub fn new(size: f32) -> Self {
+        Self {
+            lava: Lava,
+            surface: Surface::new(LAVA_FRICTION, size),
+            collider: Collider::cuboid(size / 2.0, size / 2.0),
+            sensor: Sensor,
+            coll_groups: CollGroupsConfig::hazard(),
+            active_events: ActiveEvents::COLLISION_EVENTS,
+        }
+    }
+}
+
+impl LdtkIntCell for LavaBundle {
+    fn bundle_int_cell(_: IntGridCell, layer_instance: &LayerInstance) -> Self {
+        Self::new(layer_instance.grid_size as f32)
+    }
+}
+
+pub struct LavaPlugin;
+
+impl Plugin for LavaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_step_system_to_stage(
+            GameplayStage,
+            // burns are added after this step's hits, so the statuses stack the same every run
+            lava_system
+                .after(BulletSystem)
+                .before(StatusSystem)
+                .before(HealthSystem),
+        );
+    }
+}
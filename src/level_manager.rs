@@ -1,7 +1,18 @@
 use std::collections::HashSet;
 
 use bevy::prelude::*;
+use bevy::sprite::Rect;
+use bevy_ecs_ldtk::ldtk::Level;
 use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::{CollisionGroups, Velocity};
+
+use crate::config::CollGroupsConfig;
+use crate::entity::bullet::{Bullet, BulletPool, InactiveBullet};
+use crate::health::Health;
+use crate::movement::MovementController;
+use crate::player::Player;
+use crate::replay::not_replaying;
+use crate::status::Statuses;
 
 // function to spawn specific level from ldtk
 pub fn spawn_levels(
@@ -34,3 +45,170 @@ pub fn spawn_levels(
     //     ..Default::default()
     // });
 }
+
+/// World-space rectangle of a spawned level
+fn level_rect(level: &Level, transform: &GlobalTransform) -> Rect {
+    // levels are spawned with their bottom left corner at the transform
+    let min = transform.translation().truncate();
+
+    Rect {
+        min,
+        max: min + Vec2::new(level.px_wid as f32, level.px_hei as f32),
+    }
+}
+
+fn rect_contains(rect: &Rect, point: Vec2) -> bool {
+    point.x >= rect.min.x && point.x <= rect.max.x && point.y >= rect.min.y && point.y <= rect.max.y
+}
+
+/// World-space rectangles of the spawned levels
+pub fn level_rects(
+    level_query: &Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: &Assets<LdtkLevel>,
+) -> Vec<Rect> {
+    level_query
+        .iter()
+        .filter_map(|(handle, transform)| Some(level_rect(&levels.get(handle)?.level, transform)))
+        .collect()
+}
+
+/// The level rectangle containing the point
+pub fn level_rect_at(rects: &[Rect], point: Vec2) -> Option<Rect> {
+    rects
+        .iter()
+        .copied()
+        .find(|rect| rect_contains(rect, point))
+}
+
+/// The spawned level containing the point
+pub fn level_at<'a>(
+    level_query: &Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: &'a Assets<LdtkLevel>,
+    point: Vec2,
+) -> Option<&'a Level> {
+    level_query.iter().find_map(|(handle, transform)| {
+        let level = &levels.get(handle)?.level;
+        rect_contains(&level_rect(level, transform), point).then_some(level)
+    })
+}
+
+/// Level entity an LDtk entity was spawned in, entities are children of their level
+pub fn level_of(entity: Entity, parent_query: &Query<&Parent>) -> Option<Entity> {
+    Some(parent_query.get(entity).ok()?.get())
+}
+
+/// Value of a level's string or enum field
+pub fn level_field(level: &Level, identifier: &str) -> Option<String> {
+    let field = level
+        .field_instances
+        .iter()
+        .find(|f| f.identifier == *identifier)?;
+
+    match &field.value {
+        FieldValue::String(Some(value)) | FieldValue::Enum(Some(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Restarts a level from scratch: its enemies, the player and every bullet in play
+pub struct RestartLevelEvent {
+    /// iid of the level, the level the player is in if `None`
+    pub level_iid: Option<String>,
+}
+
+// quick retry
+fn restart_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut restart_events: EventWriter<RestartLevelEvent>,
+) {
+    if keyboard_input.just_pressed(KeyCode::R) {
+        restart_events.send(RestartLevelEvent { level_iid: None });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn restart_level_system(
+    mut commands: Commands,
+    mut restart_events: EventReader<RestartLevelEvent>,
+    level_query: Query<(Entity, &Handle<LdtkLevel>, &GlobalTransform)>,
+    mut player_query: Query<(
+        (&mut Player, &GlobalTransform, &mut Transform, &mut Velocity),
+        (&mut CollisionGroups, &mut MovementController),
+        (&mut Health, &mut Statuses),
+    )>,
+    bullet_query: Query<Entity, (With<Bullet>, Without<InactiveBullet>)>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut bullet_pool: ResMut<BulletPool>,
+) {
+    // restarting the same level twice in a frame does nothing more
+    let event = match restart_events.iter().last() {
+        Some(event) => event,
+        None => return,
+    };
+
+    let player_pos = player_query
+        .iter()
+        .find(|((player, ..), ..)| player.id == 0)
+        .map(|((_, transform, ..), ..)| transform.translation().truncate());
+
+    let level_entity = level_query.iter().find_map(|(entity, handle, transform)| {
+        let level = &levels.get(handle)?.level;
+        let found = match (&event.level_iid, player_pos) {
+            (Some(iid), _) => level.iid == *iid,
+            (None, Some(position)) => rect_contains(&level_rect(level, transform), position),
+            (None, None) => false,
+        };
+        found.then_some(entity)
+    });
+
+    let level_entity = match level_entity {
+        Some(entity) => entity,
+        None => {
+            println!("no level to restart");
+            return;
+        }
+    };
+
+    // players are worldly and survive level respawns, they start over where they first started
+    for (
+        (mut player, _, mut transform, mut velocity),
+        (mut groups, mut controller),
+        (mut health, mut statuses),
+    ) in player_query.iter_mut()
+    {
+        player.restart();
+
+        let position = player.respawn_position();
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        velocity.linvel = Vec2::ZERO;
+
+        // a dash cut short would leave the player passing through things, and unable to steer
+        *groups = CollGroupsConfig::player();
+        controller.lock(0.0);
+
+        health.heal_full();
+        *statuses = Statuses::default();
+    }
+
+    for entity in bullet_query.iter() {
+        bullet_pool.release(entity, &mut commands);
+    }
+
+    commands.entity(level_entity).insert(Respawn);
+}
+
+pub struct LevelManagerPlugin;
+
+impl Plugin for LevelManagerPlugin {
+    fn build(&self, app: &mut App) {
+        // runs after the gameplay stage and before ldtk respawns the level in `LdtkStage::ProcessApi`
+        app.add_event::<RestartLevelEvent>()
+            .add_system(
+                restart_input_system
+                    .with_run_criteria(not_replaying)
+                    .before(restart_level_system),
+            )
+            .add_system(restart_level_system);
+    }
+}
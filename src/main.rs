@@ -2,23 +2,150 @@ use bevy::{prelude::*, render::camera::ScalingMode};
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+mod animation;
+mod bench;
 mod bg;
+mod bytes;
 mod camera;
+mod checkpoint;
 mod config;
+mod determinism;
 mod entity;
+mod feedback;
+mod health;
 mod input_manager;
+mod lava;
 mod level_manager;
+mod movement;
+mod net;
+mod pixel_perfect;
 mod player;
+mod quicksave;
+mod replay;
+mod save;
+mod simulation;
+mod status;
 mod wall;
 
+use checkpoint::CheckpointBundle;
 use config::*;
 use entity::bullet::BulletPlugin;
 use entity::enemy::*;
+use lava::LavaBundle;
 use level_manager::spawn_levels;
 use player::*;
 use wall::*;
 
+/// Stage with the gameplay systems, runs before physics on every fixed step
+#[derive(StageLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GameplayStage;
+
+/// Stage of the fixed step after physics, for systems that need the step's results
+#[derive(StageLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PostPhysicsStage;
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // headless bullet stress test
+    if let Some(i) = args.iter().position(|arg| arg == "--bench-bullets") {
+        let bullet_count = args
+            .get(i + 1)
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(5000);
+
+        bench::run_bullet_bench(bullet_count);
+        return;
+    }
+
+    // a replay brings its own seed
+    let replay = args
+        .iter()
+        .position(|arg| arg == "--replay")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| match replay::Replay::load(&path.into()) {
+            Ok(replay) => replay,
+            Err(e) => {
+                println!("could not load replay {}: {}", path, e);
+                std::process::exit(1);
+            }
+        });
+
+    // same seed, same game
+    let seed = match &replay {
+        Some(replay) => replay.seed,
+        None => args
+            .iter()
+            .position(|arg| arg == "--seed")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random),
+    };
+    println!("seed: {}", seed);
+
+    // headless check that two runs with the same seed end up the same
+    if let Some(i) = args.iter().position(|arg| arg == "--check-determinism") {
+        let steps = args
+            .get(i + 1)
+            .and_then(|steps| steps.parse().ok())
+            .unwrap_or(600);
+
+        if !determinism::check_determinism(seed, steps) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // a replay brings its own facing mode too
+    let facing_mode = match &replay {
+        Some(replay) => replay.facing_mode,
+        None => match args.iter().position(|arg| arg == "--facing") {
+            Some(i) => match args.get(i + 1).and_then(|name| FacingMode::from_name(name)) {
+                Some(facing_mode) => facing_mode,
+                None => {
+                    println!("--facing needs aim, movement or blend");
+                    std::process::exit(1);
+                }
+            },
+            None => FacingMode::default(),
+        },
+    };
+
+    // online co-op
+    let net_role = if let Some(i) = args.iter().position(|arg| arg == "--host") {
+        net::NetRole::Host {
+            port: args
+                .get(i + 1)
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(net::DEFAULT_PORT),
+        }
+    } else if let Some(i) = args.iter().position(|arg| arg == "--join") {
+        match args.get(i + 1).and_then(|host| host.parse().ok()) {
+            Some(host) => net::NetRole::Client { host },
+            None => {
+                println!(
+                    "--join needs the host's address, like 127.0.0.1:{}",
+                    net::DEFAULT_PORT
+                );
+                std::process::exit(1);
+            }
+        }
+    } else {
+        net::NetRole::Offline
+    };
+
+    let replay_mode = match replay {
+        Some(replay) => replay::ReplayMode::Play(replay),
+        None => match args.iter().position(|arg| arg == "--record") {
+            Some(i) => replay::ReplayMode::Record {
+                path: args.get(i + 1).map_or("replay.asrp", |path| path).into(),
+                seed,
+                facing_mode,
+            },
+            None => replay::ReplayMode::Off,
+        },
+    };
+
     App::new()
         .add_plugins(DefaultPlugins)
         // ldtk
@@ -35,14 +162,38 @@ fn main() {
         // pixel art camera setup
         .insert_resource(bevy::render::texture::ImageSettings::default_nearest())
         // rapier
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        .add_plugin(simulation::SimulationPlugin {
+            seed,
+            headless: false,
+        })
+        .insert_resource(facing_mode)
         // plugins
         .add_plugin(bg::BgPlugin)
         .add_plugin(SetupPlugin)
+        .add_plugin(input_manager::InputPlugin)
+        // before saves, they turn them off
+        .add_plugin(replay::ReplayPlugin { mode: replay_mode })
+        .add_plugin(net::NetPlugin { role: net_role })
+        .add_plugin(level_manager::LevelManagerPlugin)
+        .add_plugin(movement::MovementPlugin)
+        .add_plugin(lava::LavaPlugin)
+        .add_plugin(checkpoint::CheckpointPlugin)
+        .add_plugin(save::SavePlugin)
+        .add_plugin(quicksave::QuicksavePlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(animation::AnimationPlugin)
         .add_plugin(BulletPlugin)
+        .add_plugin(entity::gun::GunPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(health::HealthPlugin)
+        .add_plugin(status::StatusPlugin)
         .add_plugin(camera::CameraPlugin)
+        .add_plugin(feedback::FeedbackPlugin)
+        .add_plugin(pixel_perfect::PixelPerfectPlugin)
         .run();
 }
 
@@ -50,14 +201,17 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut windows: Re
     let window = windows.get_primary_mut().unwrap();
 
     // Add a 2D Camera
-    commands.spawn_bundle(Camera2dBundle {
-        projection: OrthographicProjection {
-            scaling_mode: ScalingMode::FixedVertical(WINDOW_HEIGHT / CAMERA_SCALE),
+    commands
+        .spawn_bundle(Camera2dBundle {
+            projection: OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical(WINDOW_HEIGHT / CAMERA_SCALE),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0, 1000.0),
             ..Default::default()
-        },
-        transform: Transform::from_xyz(WINDOW_WIDTH / 2.0, WINDOW_HEIGHT / 2.0, 1000.0),
-        ..Default::default()
-    });
+        })
+        .insert(camera::CameraController::default())
+        .insert(feedback::ScreenShake::default());
 
     // change cursor to crosshair
     window.set_cursor_icon(bevy::window::CursorIcon::Crosshair);
@@ -95,8 +249,10 @@ impl Plugin for LDtkSetup {
                 ..Default::default()
             })
             .register_ldtk_int_cell::<WallBundle>(1)
+            .register_ldtk_int_cell::<LavaBundle>(2)
             .register_ldtk_entity::<EnemyBundle>("Snake_Enemy")
             .register_ldtk_entity::<PlayerBundle>("Player")
+            .register_ldtk_entity::<CheckpointBundle>("Checkpoint")
             // ldtk systems
             .add_system(spawn_wall_colliders)
             .add_startup_system(spawn_levels);
@@ -0,0 +1,206 @@
+use bevy::ecs::query::WorldQuery;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::simulation::{FixedTime, StepSystemAppExt};
+use crate::{GameplayStage, PostPhysicsStage};
+
+/// Accelerates a rigid body towards the desired movement direction instead of setting its
+/// velocity directly, so impulses (dash, knockback) carry over into normal movement.
+#[derive(Component, Clone, Debug)]
+pub struct MovementController {
+    /// desired movement direction, set every frame by whatever drives the entity
+    pub input: Vec2,
+    pub max_speed: f32,
+    /// how fast the entity gets up to speed, in pixels per second squared
+    pub acceleration: f32,
+    /// how fast the entity slows down with no input, in pixels per second squared
+    pub deceleration: f32,
+    /// friction of the surface the entity is standing on
+    pub surface_friction: f32,
+    /// while running, the controller leaves the velocity to the physics
+    locked: Timer,
+}
+
+impl MovementController {
+    pub fn new(max_speed: f32, acceleration: f32, deceleration: f32) -> Self {
+        let mut locked = Timer::from_seconds(0.0, false);
+        locked.tick(std::time::Duration::ZERO);
+
+        Self {
+            input: Vec2::ZERO,
+            max_speed,
+            acceleration,
+            deceleration,
+            surface_friction: DEFAULT_SURFACE_FRICTION,
+            locked,
+        }
+    }
+
+    /// Stops steering the entity for `seconds`, e.g. for the length of a dash
+    pub fn lock(&mut self, seconds: f32) {
+        self.locked = Timer::from_seconds(seconds, false);
+    }
+
+    /// Seconds until the controller takes input again
+    pub fn lock_remaining(&self) -> f32 {
+        // not from the percentage, which is NaN for the unlocked zero length timer
+        self.locked
+            .duration()
+            .saturating_sub(self.locked.elapsed())
+            .as_secs_f32()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        !self.locked.finished()
+    }
+}
+
+impl Default for MovementController {
+    fn default() -> Self {
+        Self::new(300.0, 3000.0, 2400.0)
+    }
+}
+
+/// How an entity takes being pushed around by hits
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Knockback {
+    /// share of the knockback ignored, 0.0 takes all of it and 1.0 none
+    pub resistance: f32,
+    /// how long the entity loses control after a hit, in seconds
+    pub control_loss: f32,
+}
+
+impl Knockback {
+    pub fn new(resistance: f32, control_loss: f32) -> Self {
+        Self {
+            resistance,
+            control_loss,
+        }
+    }
+}
+
+impl Default for Knockback {
+    fn default() -> Self {
+        Self::new(0.0, 0.2)
+    }
+}
+
+/// What's needed to knock an entity back
+#[derive(WorldQuery)]
+#[world_query(mutable)]
+pub struct Knockable<'w> {
+    knockback: &'w Knockback,
+    impulse: &'w mut ExternalImpulse,
+    mass: &'w ReadMassProperties,
+    controller: Option<&'w mut MovementController>,
+}
+
+impl<'w, 's> KnockableItem<'w, 's> {
+    /// Pushes the entity in the direction, `strength` is the change in speed before resistance
+    pub fn push(&mut self, direction: Vec2, strength: f32) {
+        let strength = strength * (1.0 - self.knockback.resistance).max(0.0);
+        if strength <= 0.0 {
+            return;
+        }
+
+        self.impulse.impulse += direction * strength * self.mass.0.mass;
+
+        // a longer lock, like a dash, isn't cut short
+        if let Some(controller) = self.controller.as_mut() {
+            if controller.lock_remaining() < self.knockback.control_loss {
+                controller.lock(self.knockback.control_loss);
+            }
+        }
+    }
+}
+
+/// Friction used when the entity isn't standing on any [`Surface`] tile
+pub const DEFAULT_SURFACE_FRICTION: f32 = 1.0;
+
+/// A floor tile that changes how fast entities speed up and slow down on it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Surface {
+    /// multiplier for the acceleration and deceleration of entities standing on the tile
+    pub friction: f32,
+    half_size: f32,
+}
+
+impl Surface {
+    /// A tile `size` pixels wide
+    pub fn new(friction: f32, size: f32) -> Self {
+        Self {
+            friction,
+            half_size: size / 2.0,
+        }
+    }
+}
+
+// impulses aren't cleared by rapier, so clear them once physics has applied them
+// and let every source add to them
+fn reset_impulses(mut impulse_query: Query<&mut ExternalImpulse>) {
+    for mut impulse in impulse_query.iter_mut() {
+        if impulse.impulse != Vec2::ZERO {
+            impulse.impulse = Vec2::ZERO;
+        }
+    }
+}
+
+fn surface_system(
+    mut controller_query: Query<(&mut MovementController, &GlobalTransform)>,
+    surface_query: Query<(&Surface, &GlobalTransform)>,
+) {
+    for (mut controller, transform) in controller_query.iter_mut() {
+        let position = transform.translation().truncate();
+
+        let friction = surface_query
+            .iter()
+            .find(|(surface, surface_transform)| {
+                let delta = (position - surface_transform.translation().truncate()).abs();
+                delta.x <= surface.half_size && delta.y <= surface.half_size
+            })
+            .map_or(DEFAULT_SURFACE_FRICTION, |(surface, _)| surface.friction);
+
+        if controller.surface_friction != friction {
+            controller.surface_friction = friction;
+        }
+    }
+}
+
+pub fn movement_system(
+    mut controller_query: Query<(&mut MovementController, &mut Velocity)>,
+    time: Res<FixedTime>,
+) {
+    for (mut controller, mut velocity) in controller_query.iter_mut() {
+        controller.locked.tick(time.delta());
+        if controller.is_locked() {
+            continue;
+        }
+
+        let target = controller.input.clamp_length_max(1.0) * controller.max_speed;
+        let rate = match controller.input == Vec2::ZERO {
+            true => controller.deceleration,
+            false => controller.acceleration,
+        } * controller.surface_friction;
+
+        // move the velocity towards the target by at most rate * delta
+        let delta = target - velocity.linvel;
+        let max_change = rate * time.delta_seconds();
+
+        if delta.length() <= max_change {
+            velocity.linvel = target;
+        } else {
+            velocity.linvel += delta.normalize() * max_change;
+        }
+    }
+}
+
+pub struct MovementPlugin;
+
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_step_system_to_stage(PostPhysicsStage, reset_impulses)
+            .add_step_system_to_stage(GameplayStage, surface_system.before(movement_system))
+            .add_step_system_to_stage(GameplayStage, movement_system);
+    }
+}
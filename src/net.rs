@@ -0,0 +1,640 @@
+// online co-op: the host runs the game and the physics, clients send their input and show
+// what the host sends back, predicting their own player so it moves without waiting on the host
+// run a host with `cargo run -- --host [port]` and join it with `cargo run -- --join 127.0.0.1:7777`
+
+pub mod protocol;
+
+use std::collections::{HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::config::FIXED_TIMESTEP;
+use crate::entity::bullet::{Bullet, BulletTextures, BulletType, InactiveBullet};
+use crate::entity::enemy::Enemy;
+use crate::input_manager::{input_system, InputDevice, InputSystem, LiveInput, PlayerInput};
+use crate::player::animation::player_atlas;
+use crate::player::{JoinEvent, LeaveEvent, Player, MAX_PLAYERS};
+use crate::save::SavingEnabled;
+use crate::simulation::{FixedTime, StepSystemAppExt};
+use crate::{GameplayStage, PostPhysicsStage};
+
+use self::protocol::*;
+
+pub static DEFAULT_PORT: u16 = 7777;
+// the host sends a snapshot this often, in steps
+static SNAPSHOT_INTERVAL: u64 = 2;
+// seconds without a datagram before the other side is taken to be gone
+static TIMEOUT: f64 = 5.0;
+// seconds between attempts to join
+static HELLO_INTERVAL: f32 = 1.0;
+// steps of input in every datagram from a client
+static INPUT_REDUNDANCY: usize = 4;
+// predicted steps kept to compare with the host
+static HISTORY_LENGTH: usize = 120;
+// prediction errors bigger than this are snapped instead of smoothed, in pixels
+static SNAP_DISTANCE: f32 = 48.0;
+// part of the prediction error fixed on each snapshot
+static CORRECTION: f32 = 0.3;
+
+pub enum NetRole {
+    Offline,
+    Host { port: u16 },
+    Client { host: SocketAddr },
+}
+
+// every datagram waiting on the socket
+fn receive_all(socket: &UdpSocket) -> Vec<(Vec<u8>, SocketAddr)> {
+    let mut datagrams = Vec::new();
+    let mut buffer = [0; 65536];
+
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((len, addr)) => datagrams.push((buffer[..len].to_vec(), addr)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            // a client that went away makes some platforms report an error, skip it
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                println!("network error: {}", e);
+                break;
+            }
+        }
+    }
+
+    datagrams
+}
+
+fn bind(addr: &str) -> UdpSocket {
+    let socket = match UdpSocket::bind(addr) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("could not bind {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    socket.set_nonblocking(true).unwrap();
+    socket
+}
+
+struct RemoteClient {
+    addr: SocketAddr,
+    /// device of the client's player
+    number: u16,
+    last_seen: f64,
+    /// last step of input applied
+    ack: u32,
+}
+
+/// The host side, with the clients that joined
+pub struct NetHost {
+    socket: UdpSocket,
+    clients: Vec<RemoteClient>,
+    next_number: u16,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn host_receive_system(
+    mut host: ResMut<NetHost>,
+    mut live: ResMut<LiveInput>,
+    player_query: Query<&InputDevice, With<Player>>,
+    (mut join_events, mut leave_events): (EventWriter<JoinEvent>, EventWriter<LeaveEvent>),
+    time: Res<Time>,
+) {
+    let host = &mut *host;
+    let now = time.seconds_since_startup();
+
+    for (bytes, addr) in receive_all(&host.socket) {
+        let message = match ClientMessage::from_bytes(&bytes) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        let client = host.clients.iter_mut().find(|client| client.addr == addr);
+
+        match (message, client) {
+            (ClientMessage::Hello, Some(client)) => client.last_seen = now,
+            (ClientMessage::Hello, None) => {
+                let local_players = player_query
+                    .iter()
+                    .filter(|device| !matches!(device, InputDevice::Remote(_)))
+                    .count();
+
+                if local_players + host.clients.len() >= MAX_PLAYERS {
+                    let _ = host.socket.send_to(&HostMessage::Refused.to_bytes(), addr);
+                    continue;
+                }
+
+                println!("{} connected.", addr);
+                let number = host.next_number;
+                host.next_number += 1;
+                host.clients.push(RemoteClient {
+                    addr,
+                    number,
+                    last_seen: now,
+                    ack: 0,
+                });
+                join_events.send(JoinEvent {
+                    device: InputDevice::Remote(number),
+                });
+            }
+            (ClientMessage::Input(inputs), Some(client)) => {
+                client.last_seen = now;
+                for step_input in inputs {
+                    // repeats of inputs already applied are skipped
+                    if step_input.step > client.ack {
+                        live.merge(InputDevice::Remote(client.number), step_input.input);
+                        client.ack = step_input.step;
+                    }
+                }
+            }
+            (ClientMessage::Bye, Some(client)) => client.last_seen = f64::MIN,
+            _ => {}
+        }
+    }
+
+    // clients that said goodbye or went quiet
+    host.clients.retain(|client| {
+        let gone = now - client.last_seen > TIMEOUT;
+        if gone {
+            println!("{} disconnected.", client.addr);
+            let device = InputDevice::Remote(client.number);
+            live.remove(device);
+            leave_events.send(LeaveEvent { device });
+        }
+        !gone
+    });
+}
+
+// direction of a transform, as an angle
+fn rotation_angle(transform: &Transform) -> f32 {
+    let direction = transform.rotation * Vec3::X;
+    direction.y.atan2(direction.x)
+}
+
+fn host_send_system(
+    host: Res<NetHost>,
+    fixed_time: Res<FixedTime>,
+    player_query: Query<(&Player, &InputDevice, &Transform, &TextureAtlasSprite)>,
+    enemy_query: Query<(Entity, &GlobalTransform, &Sprite), With<Enemy>>,
+    bullet_query: Query<(Entity, &Bullet, &Transform), Without<InactiveBullet>>,
+) {
+    if host.clients.is_empty() || !fixed_time.steps.is_multiple_of(SNAPSHOT_INTERVAL) {
+        return;
+    }
+
+    // players first, so they're in the first part of the snapshot
+    let mut entities: Vec<NetEntity> = player_query
+        .iter()
+        .map(|(player, _, transform, sprite)| NetEntity {
+            kind: NetKind::Player,
+            id: player.id as u64,
+            translation: transform.translation,
+            rotation: 0.0,
+            frame: sprite.index as u8,
+        })
+        .collect();
+
+    entities.extend(
+        enemy_query
+            .iter()
+            .map(|(entity, transform, sprite)| NetEntity {
+                kind: NetKind::Enemy,
+                id: entity.to_bits(),
+                translation: transform.translation(),
+                rotation: 0.0,
+                frame: sprite.flip_x as u8,
+            }),
+    );
+
+    entities.extend(
+        bullet_query
+            .iter()
+            .map(|(entity, bullet, transform)| NetEntity {
+                kind: match bullet.type_() {
+                    BulletType::Player => NetKind::PlayerBullet,
+                    BulletType::Enemy => NetKind::EnemyBullet,
+                },
+                id: entity.to_bits(),
+                translation: transform.translation,
+                rotation: rotation_angle(transform),
+                frame: 0,
+            }),
+    );
+
+    for client in host.clients.iter() {
+        let snapshot = NetSnapshot::new(
+            fixed_time.steps as u32,
+            client.ack,
+            player_query
+                .iter()
+                .find(|(_, device, _, _)| **device == InputDevice::Remote(client.number))
+                .map(|(player, _, _, _)| player.id as u8),
+            entities.clone(),
+        );
+
+        for part in snapshot.split() {
+            let bytes = HostMessage::Snapshot(part).to_bytes();
+            if let Err(e) = host.socket.send_to(&bytes, client.addr) {
+                println!("could not send to {}: {}", client.addr, e);
+                break;
+            }
+        }
+    }
+}
+
+/// The client side, connected to a host
+pub struct NetClient {
+    socket: UdpSocket,
+    host: SocketAddr,
+    /// id of our player on the host, once joined
+    player_id: Option<u8>,
+    /// step of the newest snapshot, older ones arriving late are dropped
+    last_step: Option<u32>,
+    /// parts of the newest snapshot that arrived, and the entities in them
+    parts_received: HashSet<u16>,
+    seen: HashSet<(NetKind, u64)>,
+    last_heard: f64,
+    lost: bool,
+    hello_timer: Timer,
+    /// inputs not yet known to be at the host
+    inputs: VecDeque<StepInput>,
+    /// where the local player was predicted to be after each step
+    history: VecDeque<(u32, Vec2)>,
+    proxies: HashMap<(NetKind, u64), Entity>,
+}
+
+/// Something the host simulates, shown on the client between the last two snapshots
+#[derive(Component)]
+pub struct NetProxy {
+    from: Vec3,
+    to: Vec3,
+    /// progress from `from` to `to`, 1.0 once there
+    t: f32,
+}
+
+/// Textures of the entities the client shows for the host
+pub struct ProxyTextures {
+    player: Handle<TextureAtlas>,
+    enemy: Handle<Image>,
+}
+
+impl FromWorld for ProxyTextures {
+    fn from_world(world: &mut World) -> Self {
+        let sheet = world.resource::<AssetServer>().load("player/sheet.png");
+        let enemy = world.resource::<AssetServer>().load("enemy/snake.png");
+
+        let player = world
+            .resource_mut::<Assets<TextureAtlas>>()
+            .add(player_atlas(sheet));
+
+        Self { player, enemy }
+    }
+}
+
+fn spawn_proxy(
+    entity: &NetEntity,
+    textures: &ProxyTextures,
+    bullet_textures: &BulletTextures,
+    commands: &mut Commands,
+) -> Entity {
+    let transform = Transform::from_translation(entity.translation)
+        .with_rotation(Quat::from_rotation_z(entity.rotation));
+    let proxy = NetProxy {
+        from: entity.translation,
+        to: entity.translation,
+        t: 1.0,
+    };
+
+    let bullet_texture = match entity.kind {
+        NetKind::Player => {
+            return commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: textures.player.clone(),
+                    sprite: TextureAtlasSprite::new(entity.frame as usize),
+                    transform,
+                    ..Default::default()
+                })
+                .insert(proxy)
+                .id()
+        }
+        NetKind::Enemy => {
+            return commands
+                .spawn_bundle(SpriteBundle {
+                    texture: textures.enemy.clone(),
+                    sprite: Sprite {
+                        flip_x: entity.frame != 0,
+                        ..Default::default()
+                    },
+                    transform,
+                    ..Default::default()
+                })
+                .insert(proxy)
+                .id()
+        }
+        NetKind::PlayerBullet => bullet_textures.player.clone(),
+        NetKind::EnemyBullet => bullet_textures.enemy.clone(),
+    };
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: bullet_texture,
+            transform,
+            ..Default::default()
+        })
+        .insert(proxy)
+        .id()
+}
+
+// moves the local player by part of the difference between what the host has and what we predicted
+fn reconcile(client: &mut NetClient, transform: &mut Transform, host_position: Vec2, ack: u32) {
+    let predicted = match client.history.iter().find(|(step, _)| *step == ack) {
+        Some((_, position)) => *position,
+        None => return,
+    };
+    client.history.retain(|(step, _)| *step > ack);
+
+    let error = host_position - predicted;
+    if error.length() > SNAP_DISTANCE {
+        transform.translation.x = host_position.x;
+        transform.translation.y = host_position.y;
+        client.history.clear();
+        return;
+    }
+
+    let correction = error * CORRECTION;
+    transform.translation.x += correction.x;
+    transform.translation.y += correction.y;
+    // later predictions started from the corrected position
+    for (_, position) in client.history.iter_mut() {
+        *position += correction;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn client_receive_system(
+    mut commands: Commands,
+    mut client: ResMut<NetClient>,
+    mut proxy_query: Query<(
+        &mut NetProxy,
+        &mut Transform,
+        Option<&mut TextureAtlasSprite>,
+        Option<&mut Sprite>,
+    )>,
+    mut player_query: Query<(&Player, &mut Transform), Without<NetProxy>>,
+    (textures, bullet_textures): (Res<ProxyTextures>, Res<BulletTextures>),
+    time: Res<Time>,
+) {
+    let client = &mut *client;
+    let now = time.seconds_since_startup();
+
+    // ask to join until the host answers
+    if client.player_id.is_none() {
+        client.hello_timer.tick(time.delta());
+        if client.hello_timer.just_finished() {
+            let _ = client
+                .socket
+                .send_to(&ClientMessage::Hello.to_bytes(), client.host);
+        }
+    }
+
+    for (bytes, addr) in receive_all(&client.socket) {
+        if addr != client.host {
+            continue;
+        }
+
+        let snapshot = match HostMessage::from_bytes(&bytes) {
+            Ok(HostMessage::Snapshot(snapshot)) => snapshot,
+            Ok(HostMessage::Refused) => {
+                println!("The game at {} is full.", client.host);
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        // the other parts of the newest snapshot are still welcome
+        match client.last_step {
+            Some(step) if snapshot.step < step => continue,
+            Some(step) if snapshot.step == step => {
+                if !client.parts_received.insert(snapshot.part) {
+                    continue;
+                }
+            }
+            _ => {
+                client.last_step = Some(snapshot.step);
+                client.parts_received.clear();
+                client.parts_received.insert(snapshot.part);
+                client.seen.clear();
+            }
+        }
+        client.last_heard = now;
+        client.lost = false;
+
+        if let (None, Some(id)) = (client.player_id, snapshot.player_id) {
+            println!("Joined as player {}.", id + 1);
+        }
+        client.player_id = snapshot.player_id;
+
+        for entity in snapshot.entities.iter() {
+            // our own player is predicted, not shown from the snapshot
+            if entity.kind == NetKind::Player && Some(entity.id as u8) == client.player_id {
+                if let Some((_, mut transform)) =
+                    player_query.iter_mut().find(|(player, _)| player.id == 0)
+                {
+                    reconcile(
+                        client,
+                        &mut transform,
+                        entity.translation.truncate(),
+                        snapshot.ack,
+                    );
+                }
+                continue;
+            }
+
+            let key = (entity.kind, entity.id);
+            client.seen.insert(key);
+
+            let proxy = client
+                .proxies
+                .get(&key)
+                .and_then(|proxy| proxy_query.get_mut(*proxy).ok());
+
+            match proxy {
+                Some((mut proxy, mut transform, atlas_sprite, sprite)) => {
+                    proxy.from = transform.translation;
+                    proxy.to = entity.translation;
+                    proxy.t = 0.0;
+
+                    // pooled bullets come back somewhere else, don't slide them over
+                    if proxy.from.distance(proxy.to) > SNAP_DISTANCE {
+                        proxy.from = proxy.to;
+                        transform.translation = proxy.to;
+                    }
+
+                    transform.rotation = Quat::from_rotation_z(entity.rotation);
+                    if let Some(mut atlas_sprite) = atlas_sprite {
+                        atlas_sprite.index = entity.frame as usize;
+                    }
+                    if let (Some(mut sprite), NetKind::Enemy) = (sprite, entity.kind) {
+                        sprite.flip_x = entity.frame != 0;
+                    }
+                }
+                None => {
+                    let proxy = spawn_proxy(entity, &textures, &bullet_textures, &mut commands);
+                    client.proxies.insert(key, proxy);
+                }
+            }
+        }
+
+        // gone on the host, only known once every part of the snapshot is in,
+        // a lost part leaves the proxies as they are until a later snapshot arrives whole
+        if client.parts_received.len() < snapshot.parts as usize {
+            continue;
+        }
+        let seen = &client.seen;
+        client.proxies.retain(|key, proxy| {
+            let keep = seen.contains(key);
+            if !keep {
+                commands.entity(*proxy).despawn();
+            }
+            keep
+        });
+    }
+
+    if client.player_id.is_some() && !client.lost && now - client.last_heard > TIMEOUT {
+        println!("Lost the connection to {}.", client.host);
+        client.lost = true;
+    }
+}
+
+// sends the local player's input to the host
+fn client_send_system(
+    mut client: ResMut<NetClient>,
+    fixed_time: Res<FixedTime>,
+    mut input_query: Query<(&Player, &mut PlayerInput)>,
+) {
+    if client.player_id.is_none() {
+        return;
+    }
+
+    let mut input = match input_query.iter_mut().find(|(player, _)| player.id == 0) {
+        Some((_, input)) => input,
+        None => return,
+    };
+
+    client.inputs.push_back(StepInput {
+        step: fixed_time.steps as u32,
+        input: *input,
+    });
+    while client.inputs.len() > INPUT_REDUNDANCY {
+        client.inputs.pop_front();
+    }
+
+    let bytes = ClientMessage::Input(client.inputs.iter().copied().collect()).to_bytes();
+    let _ = client.socket.send_to(&bytes, client.host);
+
+    // the host fires and swings, the bullets come back in snapshots
+    input.fire = false;
+    input.melee = false;
+}
+
+fn client_history_system(
+    mut client: ResMut<NetClient>,
+    fixed_time: Res<FixedTime>,
+    player_query: Query<(&Player, &Transform)>,
+) {
+    if let Some((_, transform)) = player_query.iter().find(|(player, _)| player.id == 0) {
+        client
+            .history
+            .push_back((fixed_time.steps as u32, transform.translation.truncate()));
+        while client.history.len() > HISTORY_LENGTH {
+            client.history.pop_front();
+        }
+    }
+}
+
+fn proxy_interpolation_system(
+    mut proxy_query: Query<(&mut NetProxy, &mut Transform)>,
+    time: Res<Time>,
+) {
+    let interval = SNAPSHOT_INTERVAL as f32 * FIXED_TIMESTEP;
+
+    for (mut proxy, mut transform) in proxy_query.iter_mut() {
+        if proxy.t >= 1.0 {
+            continue;
+        }
+        proxy.t = (proxy.t + time.delta_seconds() / interval).min(1.0);
+        transform.translation = proxy.from.lerp(proxy.to, proxy.t);
+    }
+}
+
+// the level's enemies are the host's, the client only shows them
+fn client_enemy_system(mut commands: Commands, enemy_query: Query<Entity, Added<Enemy>>) {
+    for entity in enemy_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn client_exit_system(client: Res<NetClient>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.iter().next().is_some() {
+        let _ = client
+            .socket
+            .send_to(&ClientMessage::Bye.to_bytes(), client.host);
+    }
+}
+
+pub struct NetPlugin {
+    pub role: NetRole,
+}
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.role {
+            NetRole::Offline => {}
+            NetRole::Host { port } => {
+                let addr = format!("0.0.0.0:{}", port);
+                println!("Hosting on {}.", addr);
+
+                app.insert_resource(NetHost {
+                    socket: bind(&addr),
+                    clients: Vec::new(),
+                    next_number: 0,
+                })
+                .add_system_to_stage(CoreStage::PreUpdate, host_receive_system)
+                // after physics, so snapshots have this step's positions
+                .add_step_system_to_stage(PostPhysicsStage, host_send_system);
+            }
+            NetRole::Client { host } => {
+                println!("Joining {}, saving is off.", host);
+
+                app.insert_resource(NetClient {
+                    socket: bind("0.0.0.0:0"),
+                    host: *host,
+                    player_id: None,
+                    last_step: None,
+                    parts_received: HashSet::new(),
+                    seen: HashSet::new(),
+                    last_heard: 0.0,
+                    lost: false,
+                    hello_timer: Timer::from_seconds(HELLO_INTERVAL, true),
+                    inputs: VecDeque::new(),
+                    history: VecDeque::new(),
+                    proxies: HashMap::default(),
+                })
+                // the host keeps the progress
+                .insert_resource(SavingEnabled(false))
+                .init_resource::<ProxyTextures>()
+                .add_system_to_stage(CoreStage::PreUpdate, client_receive_system)
+                .add_step_system_to_stage(
+                    GameplayStage,
+                    client_send_system.label(InputSystem).after(input_system),
+                )
+                .add_step_system_to_stage(PostPhysicsStage, client_history_system)
+                .add_system(proxy_interpolation_system)
+                .add_system(client_enemy_system)
+                .add_system_to_stage(CoreStage::Last, client_exit_system);
+            }
+        }
+    }
+}
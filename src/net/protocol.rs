@@ -0,0 +1,337 @@
+// messages between host and clients, each one fits in a datagram,
+// snapshots are sent as parts with `NetSnapshot::split`
+
+use bevy::prelude::*;
+
+use crate::bytes::{write_f32, write_input, write_vec3, Reader};
+use crate::input_manager::PlayerInput;
+
+static PROTOCOL_MAGIC: &[u8; 4] = b"ASNT";
+// 2 added the melee, parry and shield buttons to the inputs
+static PROTOCOL_VERSION: u8 = 2;
+/// Largest datagram sent, small enough to get through without being fragmented,
+/// snapshots with more entities than fit are split into parts
+pub static MAX_DATAGRAM: usize = 1200;
+
+// no player for the client yet
+static NO_PLAYER: u8 = u8::MAX;
+
+/// Input of a client for one of its gameplay steps
+#[derive(Clone, Copy, Debug)]
+pub struct StepInput {
+    pub step: u32,
+    pub input: PlayerInput,
+}
+
+pub enum ClientMessage {
+    /// asks to join, sent until the first snapshot arrives
+    Hello,
+    /// the last few steps of input, so a lost datagram doesn't lose a press
+    Input(Vec<StepInput>),
+    Bye,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NetKind {
+    Player,
+    Enemy,
+    PlayerBullet,
+    EnemyBullet,
+}
+
+impl NetKind {
+    fn from_u8(value: u8) -> Result<Self, String> {
+        match value {
+            0 => Ok(NetKind::Player),
+            1 => Ok(NetKind::Enemy),
+            2 => Ok(NetKind::PlayerBullet),
+            3 => Ok(NetKind::EnemyBullet),
+            v => Err(format!("unknown entity kind {}", v)),
+        }
+    }
+}
+
+/// State of an entity as the client shows it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetEntity {
+    pub kind: NetKind,
+    /// player id for players, the host's entity for the rest
+    pub id: u64,
+    pub translation: Vec3,
+    pub rotation: f32,
+    /// sheet frame of players, whether enemies are flipped
+    pub frame: u8,
+}
+
+// bytes of an entity in a snapshot
+const NET_ENTITY_SIZE: usize = 1 + 8 + 12 + 4 + 1;
+// bytes of a snapshot before its entities: header, step, ack, player, part, parts and count
+const SNAPSHOT_HEADER_SIZE: usize = 6 + 4 + 4 + 1 + 2 + 2 + 2;
+// entities in one part of a snapshot
+const ENTITIES_PER_PART: usize = (MAX_DATAGRAM - SNAPSHOT_HEADER_SIZE) / NET_ENTITY_SIZE;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetSnapshot {
+    /// host step the snapshot was taken at
+    pub step: u32,
+    /// last step of the client's input the host has
+    pub ack: u32,
+    /// id of the client's player, once it has joined
+    pub player_id: Option<u8>,
+    /// which of the snapshot's datagrams this is
+    pub part: u16,
+    pub parts: u16,
+    pub entities: Vec<NetEntity>,
+}
+
+impl NetSnapshot {
+    pub fn new(step: u32, ack: u32, player_id: Option<u8>, entities: Vec<NetEntity>) -> Self {
+        Self {
+            step,
+            ack,
+            player_id,
+            part: 0,
+            parts: 1,
+            entities,
+        }
+    }
+
+    /// Splits the snapshot into parts that each fit in a datagram, in the order of its entities
+    pub fn split(self) -> Vec<NetSnapshot> {
+        if self.entities.len() <= ENTITIES_PER_PART {
+            return vec![self];
+        }
+
+        let chunks: Vec<&[NetEntity]> = self.entities.chunks(ENTITIES_PER_PART).collect();
+        let parts = chunks.len() as u16;
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(part, entities)| NetSnapshot {
+                part: part as u16,
+                parts,
+                entities: entities.to_vec(),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+pub enum HostMessage {
+    /// the game is full
+    Refused,
+    Snapshot(NetSnapshot),
+}
+
+fn header(message_type: u8) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(PROTOCOL_MAGIC);
+    bytes.push(PROTOCOL_VERSION);
+    bytes.push(message_type);
+    bytes
+}
+
+fn read_header<'a>(bytes: &'a [u8]) -> Result<(u8, Reader<'a>), String> {
+    let mut reader = Reader::new(bytes);
+
+    if reader.take(4)? != PROTOCOL_MAGIC {
+        return Err("not a game message".to_string());
+    }
+    let version = reader.u8()?;
+    if version != PROTOCOL_VERSION {
+        return Err(format!("protocol version {} is not supported", version));
+    }
+
+    Ok((reader.u8()?, reader))
+}
+
+impl ClientMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            ClientMessage::Hello => header(0),
+            ClientMessage::Input(inputs) => {
+                let mut bytes = header(1);
+                bytes.push(inputs.len() as u8);
+                for step_input in inputs {
+                    bytes.extend_from_slice(&step_input.step.to_le_bytes());
+                    write_input(&mut bytes, &step_input.input);
+                }
+                bytes
+            }
+            ClientMessage::Bye => header(2),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (message_type, mut reader) = read_header(bytes)?;
+
+        match message_type {
+            0 => Ok(ClientMessage::Hello),
+            1 => {
+                let mut inputs = Vec::new();
+                for _ in 0..reader.u8()? {
+                    inputs.push(StepInput {
+                        step: reader.u32()?,
+                        input: reader.input()?,
+                    });
+                }
+                Ok(ClientMessage::Input(inputs))
+            }
+            2 => Ok(ClientMessage::Bye),
+            v => Err(format!("unknown client message {}", v)),
+        }
+    }
+}
+
+impl HostMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            HostMessage::Refused => header(0),
+            HostMessage::Snapshot(snapshot) => {
+                let mut bytes = header(1);
+                bytes.extend_from_slice(&snapshot.step.to_le_bytes());
+                bytes.extend_from_slice(&snapshot.ack.to_le_bytes());
+                bytes.push(snapshot.player_id.unwrap_or(NO_PLAYER));
+                bytes.extend_from_slice(&snapshot.part.to_le_bytes());
+                bytes.extend_from_slice(&snapshot.parts.to_le_bytes());
+
+                bytes.extend_from_slice(&(snapshot.entities.len() as u16).to_le_bytes());
+                for entity in snapshot.entities.iter() {
+                    bytes.push(entity.kind as u8);
+                    bytes.extend_from_slice(&entity.id.to_le_bytes());
+                    write_vec3(&mut bytes, entity.translation);
+                    write_f32(&mut bytes, entity.rotation);
+                    bytes.push(entity.frame);
+                }
+                bytes
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (message_type, mut reader) = read_header(bytes)?;
+
+        match message_type {
+            0 => Ok(HostMessage::Refused),
+            1 => {
+                let step = reader.u32()?;
+                let ack = reader.u32()?;
+                let player_id = match reader.u8()? {
+                    id if id == NO_PLAYER => None,
+                    id => Some(id),
+                };
+                let part = reader.u16()?;
+                let parts = reader.u16()?;
+
+                let mut entities = Vec::new();
+                for _ in 0..reader.u16()? {
+                    entities.push(NetEntity {
+                        kind: NetKind::from_u8(reader.u8()?)?,
+                        id: reader.u64()?,
+                        translation: reader.vec3()?,
+                        rotation: reader.f32()?,
+                        frame: reader.u8()?,
+                    });
+                }
+
+                Ok(HostMessage::Snapshot(NetSnapshot {
+                    step,
+                    ack,
+                    player_id,
+                    part,
+                    parts,
+                    entities,
+                }))
+            }
+            v => Err(format!("unknown host message {}", v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(id: u64) -> NetEntity {
+        NetEntity {
+            kind: NetKind::EnemyBullet,
+            id,
+            translation: Vec3::new(id as f32, -2.5, 10.0),
+            rotation: 1.25,
+            frame: 3,
+        }
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        let input = PlayerInput {
+            movement: Vec2::new(0.6, -0.8),
+            aim: Vec2::new(120.5, 64.0),
+            dash: true,
+            melee: true,
+            shield: true,
+            ..Default::default()
+        };
+        let bytes = ClientMessage::Input(vec![StepInput { step: 42, input }]).to_bytes();
+
+        match ClientMessage::from_bytes(&bytes) {
+            Ok(ClientMessage::Input(inputs)) => {
+                assert_eq!(inputs.len(), 1);
+                assert_eq!(inputs[0].step, 42);
+                assert_eq!(inputs[0].input, input);
+            }
+            _ => panic!("input did not come back"),
+        }
+
+        assert!(matches!(
+            ClientMessage::from_bytes(&ClientMessage::Hello.to_bytes()),
+            Ok(ClientMessage::Hello)
+        ));
+        assert!(matches!(
+            ClientMessage::from_bytes(&ClientMessage::Bye.to_bytes()),
+            Ok(ClientMessage::Bye)
+        ));
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let snapshot = NetSnapshot::new(1000, 998, Some(2), (0..5).map(entity).collect());
+        let bytes = HostMessage::Snapshot(snapshot.clone()).to_bytes();
+
+        match HostMessage::from_bytes(&bytes) {
+            Ok(HostMessage::Snapshot(read)) => assert_eq!(read, snapshot),
+            _ => panic!("snapshot did not come back"),
+        }
+        assert!(matches!(
+            HostMessage::from_bytes(&HostMessage::Refused.to_bytes()),
+            Ok(HostMessage::Refused)
+        ));
+    }
+
+    #[test]
+    fn big_snapshots_are_split_into_datagrams() {
+        let entities: Vec<NetEntity> = (0..1000).map(entity).collect();
+        let parts = NetSnapshot::new(7, 5, None, entities.clone()).split();
+
+        assert!(parts.len() > 1);
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.part as usize, i);
+            assert_eq!(part.parts as usize, parts.len());
+            assert_eq!(part.step, 7);
+            assert!(HostMessage::Snapshot(part.clone()).to_bytes().len() <= MAX_DATAGRAM);
+        }
+
+        // nothing is left out
+        let joined: Vec<NetEntity> = parts.into_iter().flat_map(|part| part.entities).collect();
+        assert_eq!(joined, entities);
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut bytes = ClientMessage::Hello.to_bytes();
+        bytes[4] = PROTOCOL_VERSION + 1;
+        assert!(ClientMessage::from_bytes(&bytes).is_err());
+        assert!(ClientMessage::from_bytes(b"ASNT").is_err());
+    }
+}
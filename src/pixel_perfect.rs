@@ -0,0 +1,221 @@
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::{CameraUpdateSystem, RenderTarget, ScalingMode};
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::render::view::RenderLayers;
+use bevy::transform::TransformSystem;
+use bevy::window::WindowId;
+
+use crate::camera::CameraController;
+use crate::config::*;
+
+// layer of the upscaled image, the game camera doesn't see it
+static UPSCALE_LAYER: u8 = 1;
+
+/// Renders the game into a low resolution image, which is shown scaled up by a whole number
+/// and letterboxed, so every art pixel covers the same square of screen pixels
+pub struct PixelPerfect {
+    pub enabled: bool,
+    /// internal resolution in world pixels
+    pub resolution: UVec2,
+    /// where the image is shown, in logical window pixels from the top left corner
+    screen_rect: Option<(Vec2, Vec2)>,
+}
+
+impl PixelPerfect {
+    /// Part of the window the game is shown in while pixel perfect, for mapping the cursor
+    pub fn screen_rect(&self) -> Option<(Vec2, Vec2)> {
+        self.screen_rect.filter(|_| self.enabled)
+    }
+}
+
+impl Default for PixelPerfect {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            resolution: UVec2::new(INTERNAL_WIDTH, INTERNAL_HEIGHT),
+            screen_rect: None,
+        }
+    }
+}
+
+/// Image the game camera renders to while pixel perfect
+struct LowResImage(Handle<Image>);
+
+/// Camera showing the upscaled image on the window
+#[derive(Component)]
+struct UpscaleCamera;
+
+#[derive(Component)]
+struct UpscaleSprite;
+
+/// Largest whole scale at which `resolution` fits in `window_size`, at least 1
+fn integer_scale(window_size: UVec2, resolution: UVec2) -> u32 {
+    (window_size.x / resolution.x)
+        .min(window_size.y / resolution.y)
+        .max(1)
+}
+
+fn setup_low_res(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    pixel_perfect: Res<PixelPerfect>,
+) {
+    let size = Extent3d {
+        width: pixel_perfect.resolution.x,
+        height: pixel_perfect.resolution.y,
+        ..Default::default()
+    };
+
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("low_res"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..Default::default()
+    };
+    image.resize(size);
+    let image = images.add(image);
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: image.clone(),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(RenderLayers::layer(UPSCALE_LAYER))
+        .insert(UpscaleSprite);
+
+    // on top of the game camera, clearing the letterbox to black
+    commands
+        .spawn_bundle(Camera2dBundle {
+            camera: Camera {
+                priority: 1,
+                is_active: false,
+                ..Default::default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::Custom(Color::BLACK),
+            },
+            ..Default::default()
+        })
+        .insert(RenderLayers::layer(UPSCALE_LAYER))
+        .insert(UpscaleCamera);
+
+    commands.insert_resource(LowResImage(image));
+}
+
+// P switches between pixel perfect and smooth rendering
+fn pixel_perfect_toggle_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut pixel_perfect: ResMut<PixelPerfect>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+        pixel_perfect.enabled = !pixel_perfect.enabled;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn pixel_perfect_system(
+    mut pixel_perfect: ResMut<PixelPerfect>,
+    windows: Res<Windows>,
+    low_res: Res<LowResImage>,
+    mut camera_query: Query<(&mut Camera, &mut OrthographicProjection), With<CameraController>>,
+    mut upscale_camera_query: Query<&mut Camera, (With<UpscaleCamera>, Without<CameraController>)>,
+    mut sprite_query: Query<(&mut Transform, &mut Visibility), With<UpscaleSprite>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = UVec2::new(window.physical_width(), window.physical_height());
+    let enabled = pixel_perfect.enabled;
+
+    let (target, scaling_mode) = match enabled {
+        true => (
+            RenderTarget::Image(low_res.0.clone()),
+            ScalingMode::FixedVertical(pixel_perfect.resolution.y as f32),
+        ),
+        false => (
+            RenderTarget::Window(WindowId::primary()),
+            ScalingMode::FixedVertical(WINDOW_HEIGHT / CAMERA_SCALE),
+        ),
+    };
+
+    // only write on changes, the projection is recalculated whenever it's changed
+    for (mut camera, mut projection) in camera_query.iter_mut() {
+        if camera.target != target {
+            camera.target = target.clone();
+            projection.scaling_mode = scaling_mode.clone();
+        }
+    }
+
+    for mut camera in upscale_camera_query.iter_mut() {
+        if camera.is_active != enabled {
+            camera.is_active = enabled;
+        }
+    }
+
+    // the image is drawn at a whole number of physical pixels per art pixel
+    let scale = integer_scale(window_size, pixel_perfect.resolution);
+    let sprite_scale = scale as f32 / window.scale_factor() as f32;
+
+    for (mut transform, mut visibility) in sprite_query.iter_mut() {
+        if visibility.is_visible != enabled {
+            visibility.is_visible = enabled;
+        }
+        if transform.scale.x != sprite_scale {
+            transform.scale = Vec3::new(sprite_scale, sprite_scale, 1.0);
+        }
+    }
+
+    // the image is centred, the rest of the window is the letterbox
+    let size = pixel_perfect.resolution.as_vec2() * sprite_scale;
+    let min = (Vec2::new(window.width(), window.height()) - size) / 2.0;
+    let screen_rect = Some((min, min + size));
+    if pixel_perfect.screen_rect != screen_rect {
+        pixel_perfect.screen_rect = screen_rect;
+    }
+}
+
+// moving the camera by fractions of an art pixel makes the pixel art shimmer
+pub fn snap_camera_system(
+    pixel_perfect: Res<PixelPerfect>,
+    mut camera_query: Query<&mut Transform, With<CameraController>>,
+) {
+    if !pixel_perfect.enabled {
+        return;
+    }
+
+    for mut transform in camera_query.iter_mut() {
+        transform.translation.x = transform.translation.x.round();
+        transform.translation.y = transform.translation.y.round();
+    }
+}
+
+pub struct PixelPerfectPlugin;
+
+impl Plugin for PixelPerfectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PixelPerfect>()
+            .add_startup_system(setup_low_res)
+            .add_system(pixel_perfect_toggle_system)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                pixel_perfect_system.before(CameraUpdateSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                snap_camera_system.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
@@ -1,48 +1,81 @@
+pub mod animation;
 pub mod gun;
+pub mod melee;
+pub mod shield;
+
+use std::time::Duration;
 
-use bevy::math::vec2;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use crate::animation::{Action, AnimationState, Animations};
+use crate::checkpoint::{PlayerRespawnEvent, RespawnPoint};
 use crate::config::*;
 use crate::entity::bullet::*;
 use crate::entity::*;
-
-use self::gun::{gun_system, Gun};
+use crate::feedback::{CameraKickEvent, FeedbackEvent};
+use crate::health::{Health, HealthSystem};
+use crate::input_manager::{InputDevice, InputSystem, PlayerInput};
+use crate::movement::{movement_system, Knockback, MovementController};
+use crate::quicksave::TimerState;
+use crate::replay::not_replaying;
+use crate::simulation::{FixedTime, GameRng, StepSystemAppExt};
+use crate::status::{StatusEffect, StatusKind, StatusSystem, Statuses};
+use crate::GameplayStage;
+
+use self::animation::{player_animation_system, player_animations, player_atlas};
+use crate::entity::gun::{Gun, GunSystem};
+
+use self::gun::player_gun_system;
+use self::melee::{melee_hitbox_system, melee_system, Melee};
+use self::shield::{shield_system, Shield};
 
 #[allow(clippy::type_complexity)]
-fn player_system(
+pub fn player_system(
     mut player_query: Query<(
         &mut Player,
         &mut Velocity,
+        &mut MovementController,
         &mut ExternalImpulse,
+        &ReadMassProperties,
         &mut CollisionGroups,
-        &mut TextureAtlasSprite,
         &mut Transform,
+        &PlayerInput,
+        &mut Health,
+        &mut Statuses,
+        Option<&Children>,
     )>,
-    q_camera: Query<(&Camera, &GlobalTransform)>,
-    gun_query: Query<(&Gun, &Transform), Without<Player>>,
-
-    (keyboard_input, buttons, windows, asset_server, time): (
-        Res<Input<KeyCode>>,
-        Res<Input<MouseButton>>,
-        Res<Windows>,
-        Res<AssetServer>,
-        Res<Time>,
+    mut gun_query: Query<&mut Gun>,
+
+    (bullet_textures, loadout, time, rapier): (
+        Res<BulletTextures>,
+        Res<Loadout>,
+        Res<FixedTime>,
+        Res<RapierContext>,
+    ),
+    (mut bullet_pool, mut rng): (ResMut<BulletPool>, ResMut<GameRng>),
+    (mut feedback_events, mut kick_events, mut respawn_events): (
+        EventWriter<FeedbackEvent>,
+        EventWriter<CameraKickEvent>,
+        EventWriter<PlayerRespawnEvent>,
     ),
 
     mut commands: Commands,
 ) {
-    let (camera, camera_transform) = q_camera.single();
-
     for (
         mut player,
         mut player_vel,
+        mut controller,
         mut player_impulse,
+        mass,
         mut player_coll_groups,
-        mut player_sprite,
         mut player_transform,
+        input,
+        mut health,
+        mut statuses,
+        children,
     ) in player_query.iter_mut()
     {
         // update player z index in a stupid way, because ldtk doesn't support z index (i think)
@@ -52,63 +85,72 @@ fn player_system(
 
         // check if player dead
         if player.dead && !player.immortal {
-            // teleport player
-            player_transform.translation.x = player.start_coords.x;
-            player_transform.translation.y = player.start_coords.y;
+            // lies there for the die animation first
+            player.death_timer.tick(time.delta());
+            if !player.death_timer.finished() {
+                controller.input = Vec2::ZERO;
+                continue;
+            }
+            // teleport player to the last checkpoint, or where they started
+            let position = player.respawn_position();
+            player_transform.translation.x = position.x;
+            player_transform.translation.y = position.y;
 
             // reset player velocity
             player_vel.linvel = Vec2 { x: 0.0, y: 0.0 };
 
-            println!("You ded.");
-            player.dead = false;
+            // and comes back whole
+            health.heal_full();
+            *statuses = Statuses::default();
+
+            println!("Player {} ded.", player.id + 1);
+            player.revive();
+            respawn_events.send(PlayerRespawnEvent {
+                position,
+                level_iid: player
+                    .respawn_point
+                    .as_ref()
+                    .and_then(|respawn| respawn.level_iid.clone()),
+            });
         }
 
         // movement
-        let up = keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up);
-        let down = keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down);
-        let left = keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left);
-        let right = keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right);
-
-        let x_axis = -(left as i8) + right as i8;
-        let y_axis = -(down as i8) + up as i8;
-
-        if x_axis != 0 {
-            player.direction = match right {
-                true => SpriteDirection::Right,
-                false => SpriteDirection::Left,
-            };
-        }
-        if y_axis != 0 {
-            player.direction = match up {
-                true => SpriteDirection::Up,
-                false => SpriteDirection::Down,
-            };
-        }
+        let move_delta = input.movement;
+
+        let mouse_pos = input.aim;
+        let player_pos = player_transform.translation.truncate();
+
+        // facing
+        player.facing =
+            player
+                .facing_mode
+                .facing(mouse_pos - player_pos, move_delta, player.facing);
 
         // dash
-        const PLAYER_DASH_SPEED: f32 = 15.0;
+        const PLAYER_DASH_SPEED: f32 = 650.0;
         player.dash_timer.tick(time.delta());
         player.dash_cooldown.tick(time.delta());
+        player.dash_charges.tick(time.delta());
 
         if player.dash_cooldown.finished()
             && !player.dashing
-            && keyboard_input.just_pressed(KeyCode::Space)
+            && input.dash
+            && player.dash_charges.use_charge()
         {
             player.dashing = true;
             player.dash_timer.reset();
-        }
 
-        if player.dashing && !player.dash_timer.finished() {
-            let direction = match player.direction {
-                SpriteDirection::Up => vec2(0.0, 1.0),
-                SpriteDirection::Down => vec2(0.0, -1.0),
-                SpriteDirection::Left => vec2(-1.0, 0.0),
-                SpriteDirection::Right => vec2(1.0, 0.0),
+            // dash where the player is moving, or where they're facing when standing still
+            let direction = match move_delta == Vec2::ZERO {
+                true => player.facing.to_vec2(),
+                false => move_delta,
             };
 
-            player_impulse.impulse = direction * PLAYER_DASH_SPEED;
+            // one impulse at the start, the controller takes over again once the dash ends
+            player_impulse.impulse += direction * PLAYER_DASH_SPEED * mass.0.mass;
+            controller.lock(player.dash_timer.duration().as_secs_f32());
 
-            player_coll_groups.filters = 0b10000;
+            player_coll_groups.filters = player.dash_rules.filters();
         }
         if player.dash_timer.just_finished() {
             player.dashing = false;
@@ -117,85 +159,300 @@ fn player_system(
             player.dash_cooldown.reset();
         }
 
-        // update sprite
-        match player.direction {
-            SpriteDirection::Right => player_sprite.index = 0,
-            SpriteDirection::Left => player_sprite.index = 1,
-            SpriteDirection::Up => player_sprite.index = 2,
-            SpriteDirection::Down => player_sprite.index = 3,
-        }
-
-        // Update the velocity on the rigid_body_component,
-        // the bevy_rapier plugin will update the Sprite transform.
-        let mut move_delta = Vec2::new(x_axis as f32, y_axis as f32);
-        if move_delta != Vec2::ZERO {
-            move_delta /= move_delta.length();
-        }
-        player_vel.linvel = move_delta * player.speed;
-
-        // shooting
-        let window = windows.get_primary().unwrap();
-
-        if buttons.just_pressed(MouseButton::Left) {
-            let mouse_pos =
-                crate::input_manager::get_mouse_world_pos(window, camera, camera_transform);
-
-            // calculate vector of length 1 to mouse position
-            let player_pos = Vec2::new(
-                player_transform.translation.x,
-                player_transform.translation.y,
-            );
-            let direction = (mouse_pos - player_pos).normalize();
-
-            // get gun transform
-            let (gun, gun_transform) = gun_query.single();
-
-            let mut bullet_spawn =
-                Vec2::new(gun_transform.translation.x, gun_transform.translation.y);
-            // adjust bullet spawn position
-            bullet_spawn += direction * gun.bullet_offset.x;
-            bullet_spawn.y += gun.bullet_offset.y;
-
-            Bullet::new(asset_server.load("bullet/player.png")).spawn(
-                bullet_spawn.x,
-                bullet_spawn.y,
-                direction,
-                &mut commands,
-            );
+        // the movement controller accelerates the player towards this direction
+        controller.input = move_delta;
+
+        // shooting with the player's own gun, not while it's being swapped
+        let gun = children
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .find(|child| gun_query.contains(**child));
+        if let (true, Some(gun)) = (input.fire, gun) {
+            let mut gun = gun_query.get_mut(*gun).unwrap();
+
+            // a blocked shot hits the wall in front of the barrel
+            let bullet_spawn = gun.shot_origin(player_pos, &rapier);
+            let direction = gun.fire(player_pos, &mut *rng);
+
+            // recoil pushes the player and the camera back
+            player_impulse.impulse -= direction * gun.handling.recoil * mass.0.mass;
+            kick_events.send(CameraKickEvent(-direction * gun.handling.camera_kick));
+
+            Bullet::new(bullet_textures.player.clone())
+                .with_knockback(gun.handling.knockback)
+                .with_effect(loadout.bullet_effect.map(StatusEffect::of))
+                .spawn(
+                    bullet_spawn.x,
+                    bullet_spawn.y,
+                    direction,
+                    &mut bullet_pool,
+                    &mut commands,
+                );
+
+            feedback_events.send(FeedbackEvent::PlayerShot);
         }
     }
 }
 
 #[derive(Component, Clone, Debug)]
 pub struct Player {
+    /// 0 for the first player, the one spawned by the level and followed by saves
+    pub id: usize,
     start_coords: Vec2,
+    respawn_point: Option<RespawnPoint>,
     pub dead: bool,
+    /// how long a dead player stays down before respawning
+    death_timer: Timer,
     dashing: bool,
     dash_timer: Timer,
     dash_cooldown: Timer,
-    speed: f32,
-    direction: SpriteDirection,
+    dash_charges: DashCharges,
+    dash_rules: DashRules,
+    facing: Facing,
+    facing_mode: FacingMode,
     immortal: bool,
 }
 
 impl Player {
-    fn new(x: f32, y: f32) -> Self {
+    pub fn new(id: usize, x: f32, y: f32) -> Self {
         Self {
+            id,
             start_coords: Vec2::new(x, y),
+            respawn_point: None,
             dead: false,
+            death_timer: Timer::from_seconds(0.6, false),
             dashing: false,
             dash_timer: Timer::from_seconds(0.3, false),
             dash_cooldown: Timer::from_seconds(0.3, false),
-            speed: 300.0,
-            direction: SpriteDirection::Left,
+            dash_charges: DashCharges::new(Loadout::default().dash_charges, 1.0),
+            dash_rules: DashRules::default(),
+            facing: Facing::Left,
+            facing_mode: FacingMode::Aim,
             immortal: false,
         }
     }
+
+    /// Gets a dead player back up, without moving them
+    pub fn revive(&mut self) {
+        self.dead = false;
+        self.death_timer.reset();
+    }
+
+    pub fn set_facing_mode(&mut self, facing_mode: FacingMode) {
+        self.facing_mode = facing_mode;
+    }
+
+    /// Last checkpoint reached, if any
+    pub fn respawn_point(&self) -> Option<&RespawnPoint> {
+        self.respawn_point.as_ref()
+    }
+
+    pub fn set_respawn_point(&mut self, respawn_point: Option<RespawnPoint>) {
+        self.respawn_point = respawn_point;
+    }
+
+    pub fn state(&self) -> PlayerState {
+        PlayerState {
+            respawn_point: self.respawn_point.clone(),
+            dead: self.dead,
+            death_timer: TimerState::from_timer(&self.death_timer),
+            dashing: self.dashing,
+            dash_timer: TimerState::from_timer(&self.dash_timer),
+            dash_cooldown: TimerState::from_timer(&self.dash_cooldown),
+            dash_charges: self
+                .dash_charges
+                .recharge
+                .iter()
+                .map(TimerState::from_timer)
+                .collect(),
+            facing: self.facing,
+        }
+    }
+
+    pub fn restore(&mut self, state: &PlayerState) {
+        self.respawn_point = state.respawn_point.clone();
+        self.dead = state.dead;
+        self.death_timer = state.death_timer.to_timer(false);
+        self.dashing = state.dashing;
+        self.dash_timer = state.dash_timer.to_timer(false);
+        self.dash_cooldown = state.dash_cooldown.to_timer(false);
+        self.dash_charges.recharge = state
+            .dash_charges
+            .iter()
+            .map(|timer| timer.to_timer(false))
+            .collect();
+        self.facing = state.facing;
+    }
+
+    /// Starts the player over the way they spawned, without a checkpoint and with every dash charge ready
+    pub fn restart(&mut self) {
+        self.respawn_point = None;
+        self.revive();
+        self.dashing = false;
+        self.dash_timer.reset();
+        self.dash_cooldown.reset();
+        self.dash_charges.refill();
+    }
+
+    /// Where the player comes back, the last checkpoint or where they started
+    pub fn respawn_position(&self) -> Vec2 {
+        self.respawn_point
+            .as_ref()
+            .map_or(self.start_coords, |respawn| respawn.position)
+    }
+}
+
+/// Serialisable state of a player, for quicksaves
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub respawn_point: Option<RespawnPoint>,
+    pub dead: bool,
+    pub death_timer: TimerState,
+    pub dashing: bool,
+    pub dash_timer: TimerState,
+    pub dash_cooldown: TimerState,
+    pub dash_charges: Vec<TimerState>,
+    pub facing: Facing,
+}
+
+/// Equipment the player carries, kept between levels and runs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Loadout {
+    /// texture of the gun
+    pub gun: String,
+    pub dash_charges: usize,
+    /// lingers on what the player's bullets hit
+    #[serde(default)]
+    pub bullet_effect: Option<StatusKind>,
+    /// what dashes pass through
+    #[serde(default)]
+    pub dash_rules: DashRules,
+}
+
+impl Default for Loadout {
+    fn default() -> Self {
+        Self {
+            gun: "gun1.png".to_string(),
+            dash_charges: 2,
+            bullet_effect: None,
+            dash_rules: DashRules::default(),
+        }
+    }
+}
+
+// gives new players, and every player when it changes, the loadout's dash charges and rules
+fn loadout_system(loadout: Res<Loadout>, mut player_query: Query<&mut Player>) {
+    // change trackers can't be queried next to the component they track, `Mut` has them
+    for mut player in player_query.iter_mut() {
+        if !player.is_added() && !loadout.is_changed() {
+            continue;
+        }
+
+        if player.dash_charges.count() != loadout.dash_charges {
+            player.dash_charges = DashCharges::new(loadout.dash_charges, 1.0);
+        }
+        if player.dash_rules != loadout.dash_rules {
+            player.dash_rules = loadout.dash_rules;
+        }
+    }
+}
+
+// gives new players, and every player when it changes, the facing mode setting
+fn facing_mode_system(facing_mode: Res<FacingMode>, mut player_query: Query<&mut Player>) {
+    for mut player in player_query.iter_mut() {
+        if (player.is_added() || facing_mode.is_changed()) && player.facing_mode != *facing_mode {
+            player.set_facing_mode(*facing_mode);
+        }
+    }
+}
+
+/// Dash charges, each one recharging on its own
+#[derive(Clone, Debug)]
+pub struct DashCharges {
+    recharge: Vec<Timer>,
+}
+
+impl DashCharges {
+    pub fn new(count: usize, recharge_seconds: f32) -> Self {
+        let mut timer = Timer::from_seconds(recharge_seconds, false);
+        // start with every charge ready
+        timer.tick(Duration::from_secs_f32(recharge_seconds));
+
+        Self {
+            recharge: vec![timer; count],
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.recharge.len()
+    }
+
+    fn tick(&mut self, delta: Duration) {
+        for timer in self.recharge.iter_mut() {
+            timer.tick(delta);
+        }
+    }
+
+    fn refill(&mut self) {
+        for timer in self.recharge.iter_mut() {
+            let duration = timer.duration();
+            timer.tick(duration);
+        }
+    }
+
+    /// Spends a ready charge, returns false if there is none
+    fn use_charge(&mut self) -> bool {
+        match self.recharge.iter_mut().find(|timer| timer.finished()) {
+            Some(timer) => {
+                timer.reset();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What the player can pass through while dashing, missing rules are on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DashRules {
+    pub through_bullets: bool,
+    pub through_enemies: bool,
+    pub through_hazards: bool,
+}
+
+impl DashRules {
+    /// Collision filters of the player while dashing
+    fn filters(&self) -> u32 {
+        let mut filters = CollGroupsConfig::player().filters;
+
+        if self.through_bullets {
+            filters &= !CollGroupsConfig::bullet_enemy().memberships;
+        }
+        if self.through_enemies {
+            filters &= !CollGroupsConfig::enemy().memberships;
+        }
+        if self.through_hazards {
+            filters &= !CollGroupsConfig::hazard().memberships;
+        }
+
+        filters
+    }
+}
+
+impl Default for DashRules {
+    fn default() -> Self {
+        Self {
+            through_bullets: true,
+            through_enemies: true,
+            through_hazards: true,
+        }
+    }
 }
 
 impl Default for Player {
     fn default() -> Self {
-        Self::new(0.0, 0.0)
+        Self::new(0, 0.0, 0.0)
     }
 }
 
@@ -205,36 +462,38 @@ pub struct PlayerBundle {
     worldly: Worldly,
     #[bundle]
     entity_bundle: EntityBundle,
+    controller: MovementController,
     external_impulse: ExternalImpulse,
+    mass: ReadMassProperties,
     ccd: Ccd,
+    animations: Animations,
+    animation_state: AnimationState,
+    input: PlayerInput,
+    device: InputDevice,
+    melee: Melee,
+    shield: Shield,
+    health: Health,
+    statuses: Statuses,
+    knockback: Knockback,
 }
 
-// spawning player from LDtk
-impl LdtkEntity for PlayerBundle {
-    fn bundle_entity(
-        entity: &EntityInstance,
-        _: &LayerInstance,
-        _: Option<&Handle<Image>>,
-        _: Option<&TilesetDefinition>,
+impl PlayerBundle {
+    pub fn new(
+        player: Player,
+        device: InputDevice,
         asset_server: &AssetServer,
         textures: &mut Assets<TextureAtlas>,
-    ) -> PlayerBundle {
-        let x = entity.px.x as f32;
-        let y = WINDOW_HEIGHT - entity.px.y as f32;
+    ) -> Self {
+        let position = player.start_coords;
 
-        // spawn player - return bundle
         PlayerBundle {
-            player: Player::new(x, y),
-            worldly: Worldly::from_entity_info(entity),
+            player,
+            worldly: Worldly::default(),
             entity_bundle: EntityBundle {
                 sprite_bundle: SpriteSheetBundle {
-                    texture_atlas: textures.add(TextureAtlas::from_grid(
-                        asset_server.load("player/sheet.png"),
-                        Vec2 { x: 32.0, y: 32.0 },
-                        4,
-                        1,
-                    )),
-                    transform: Transform::from_xyz(x, y, 100.0),
+                    texture_atlas: textures
+                        .add(player_atlas(asset_server.load("player/sheet.png"))),
+                    transform: Transform::from_xyz(position.x, position.y, 100.0),
                     ..Default::default()
                 },
                 rigid_body: RigidBody::Dynamic,
@@ -248,16 +507,179 @@ impl LdtkEntity for PlayerBundle {
                 locked_axes: LockedAxes::ROTATION_LOCKED,
                 coll_groups: CollGroupsConfig::player(),
             },
+            controller: MovementController::new(300.0, 3000.0, 2400.0),
             external_impulse: ExternalImpulse::default(),
+            mass: ReadMassProperties::default(),
             ccd: Ccd { enabled: true },
+            animations: player_animations(),
+            animation_state: AnimationState::new(Action::Idle, Facing::Left),
+            input: PlayerInput::default(),
+            device,
+            melee: Melee::default(),
+            shield: Shield::default(),
+            health: Health::default(),
+            statuses: Statuses::default(),
+            knockback: Knockback::new(0.3, 0.15),
+        }
+    }
+}
+
+// spawning player from LDtk
+impl LdtkEntity for PlayerBundle {
+    fn bundle_entity(
+        entity: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        asset_server: &AssetServer,
+        textures: &mut Assets<TextureAtlas>,
+    ) -> PlayerBundle {
+        let x = entity.px.x as f32;
+        let y = WINDOW_HEIGHT - entity.px.y as f32;
+
+        // spawn player - return bundle
+        PlayerBundle {
+            worldly: Worldly::from_entity_info(entity),
+            ..PlayerBundle::new(
+                Player::new(0, x, y),
+                InputDevice::KeyboardMouse,
+                asset_server,
+                textures,
+            )
         }
     }
 }
 
+/// Most players that can play at once, locally and over the network
+pub static MAX_PLAYERS: usize = 4;
+
+/// Adds a player using the device, next to the first player
+pub struct JoinEvent {
+    pub device: InputDevice,
+}
+
+/// Removes the player using the device
+pub struct LeaveEvent {
+    pub device: InputDevice,
+}
+
+// a gamepad joins the game when start is pressed on it
+fn gamepad_join_system(
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut join_events: EventWriter<JoinEvent>,
+) {
+    for gamepad in gamepads.iter() {
+        if gamepad_buttons.just_pressed(GamepadButton::new(*gamepad, GamepadButtonType::Start)) {
+            join_events.send(JoinEvent {
+                device: InputDevice::Gamepad(*gamepad),
+            });
+        }
+    }
+}
+
+fn join_system(
+    mut commands: Commands,
+    mut join_events: EventReader<JoinEvent>,
+    mut leave_events: EventReader<LeaveEvent>,
+    player_query: Query<(Entity, &Player, &Transform, &InputDevice)>,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<Assets<TextureAtlas>>,
+) {
+    // ids and devices in use, kept up to date as players join in this frame
+    let mut taken: Vec<(usize, InputDevice)> = player_query
+        .iter()
+        .map(|(_, player, _, device)| (player.id, *device))
+        .collect();
+
+    for event in leave_events.iter() {
+        for (entity, player, _, device) in player_query.iter() {
+            if *device == event.device {
+                println!("Player {} left.", player.id + 1);
+                commands.entity(entity).despawn_recursive();
+                taken.retain(|(id, _)| *id != player.id);
+            }
+        }
+    }
+
+    for event in join_events.iter() {
+        if taken.iter().any(|(_, device)| *device == event.device) || taken.len() >= MAX_PLAYERS {
+            continue;
+        }
+
+        let (first, first_transform) = match player_query.iter().find(|(_, p, _, _)| p.id == 0) {
+            Some((_, first, transform, _)) => (first, transform),
+            None => continue,
+        };
+
+        // lowest free id
+        let id = (0..MAX_PLAYERS)
+            .find(|id| !taken.iter().any(|(taken_id, _)| taken_id == id))
+            .unwrap();
+        taken.push((id, event.device));
+
+        // starts where the first player is, and comes back to the same checkpoint
+        let mut player = Player::new(
+            id,
+            first_transform.translation.x,
+            first_transform.translation.y,
+        );
+        player.respawn_point = first.respawn_point.clone();
+
+        println!("Player {} joined.", id + 1);
+        commands.spawn_bundle(PlayerBundle::new(
+            player,
+            event.device,
+            &asset_server,
+            &mut textures,
+        ));
+    }
+}
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(player_system).add_system(gun_system);
+        app.init_resource::<Loadout>()
+            .init_resource::<FacingMode>()
+            .add_event::<JoinEvent>()
+            .add_event::<LeaveEvent>()
+            .add_system(gamepad_join_system.before(join_system))
+            // players joining and leaving aren't recorded
+            .add_system(join_system.with_run_criteria(not_replaying))
+            .add_step_system_to_stage(GameplayStage, loadout_system.before(player_system))
+            .add_step_system_to_stage(GameplayStage, facing_mode_system.before(player_system))
+            .add_step_system_to_stage(
+                GameplayStage,
+                player_system
+                    .after(InputSystem)
+                    .before(movement_system)
+                    .before(GunSystem),
+            )
+            .add_step_system_to_stage(
+                GameplayStage,
+                player_gun_system
+                    .after(InputSystem)
+                    .before(player_system)
+                    .before(GunSystem),
+            )
+            .add_step_system_to_stage(
+                GameplayStage,
+                shield_system
+                    .after(player_system)
+                    .before(BulletSystem)
+                    .before(movement_system),
+            )
+            .add_step_system_to_stage(GameplayStage, melee_system.after(player_system))
+            .add_step_system_to_stage(
+                GameplayStage,
+                melee_hitbox_system
+                    .after(melee_system)
+                    .before(BulletSystem)
+                    .before(StatusSystem)
+                    .before(HealthSystem)
+                    .before(movement_system),
+            )
+            .add_step_system_to_stage(GameplayStage, player_animation_system.after(player_system));
     }
 }
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+
+use crate::animation::{Action, AnimationClip, AnimationState, Animations};
+use crate::config::{Facing, SpriteDirection};
+use crate::health::Health;
+use crate::movement::MovementController;
+use crate::player::Player;
+
+// the sheet has a column for each direction and a row for each pose
+const SHEET_COLUMNS: usize = 4;
+const SHEET_ROWS: usize = 7;
+
+// rows of the sheet
+const STAND: usize = 0;
+const CROUCH: usize = 1;
+const STEP: usize = 2;
+const LEAN: usize = 3;
+const FLASH: usize = 4;
+const FALL: usize = 5;
+const DOWN: usize = 6;
+
+/// Atlas of `player/sheet.png`
+pub fn player_atlas(sheet: Handle<Image>) -> TextureAtlas {
+    TextureAtlas::from_grid(sheet, Vec2::new(32.0, 32.0), SHEET_COLUMNS, SHEET_ROWS)
+}
+
+// column of each direction in the sheet
+fn sheet_column(direction: SpriteDirection) -> usize {
+    match direction {
+        SpriteDirection::Right => 0,
+        SpriteDirection::Left => 1,
+        SpriteDirection::Up => 2,
+        SpriteDirection::Down => 3,
+    }
+}
+
+/// Clips of `player/sheet.png`, for each action and facing
+pub fn player_animations() -> Animations {
+    let mut animations = Animations::default();
+
+    // diagonals use the sheet's four directions until they have their own columns
+    for facing in Facing::ALL {
+        let column = sheet_column(facing.sprite_direction());
+        let frames = |rows: &[usize]| {
+            rows.iter()
+                .map(|row| row * SHEET_COLUMNS + column)
+                .collect()
+        };
+
+        animations = animations
+            .with_clip(
+                Action::Idle,
+                facing,
+                AnimationClip::new(frames(&[STAND, CROUCH]), 0.5),
+            )
+            .with_clip(
+                Action::Walk,
+                facing,
+                AnimationClip::new(frames(&[STEP, STAND, CROUCH, STAND]), 0.1),
+            )
+            .with_clip(
+                Action::Dash,
+                facing,
+                AnimationClip::new(frames(&[CROUCH, LEAN]), 0.05).once(),
+            )
+            .with_clip(
+                Action::Hurt,
+                facing,
+                AnimationClip::new(frames(&[FLASH, STAND, FLASH]), 0.08).once(),
+            )
+            .with_clip(
+                Action::Die,
+                facing,
+                AnimationClip::new(frames(&[FLASH, FALL, DOWN]), 0.12).once(),
+            );
+    }
+
+    animations
+}
+
+// picks the clip from what the player is doing
+pub fn player_animation_system(
+    mut player_query: Query<(&Player, &MovementController, &Health, &mut AnimationState)>,
+) {
+    for (player, controller, health, mut state) in player_query.iter_mut() {
+        let action = if player.dead {
+            Action::Die
+        } else if health.is_hurt() {
+            Action::Hurt
+        } else if player.dashing {
+            Action::Dash
+        } else if controller.input != Vec2::ZERO {
+            Action::Walk
+        } else {
+            Action::Idle
+        };
+
+        state.play(action, player.facing);
+    }
+}
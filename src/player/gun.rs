@@ -1,84 +1,84 @@
-// TODO: add collision to gun
-
 use bevy::prelude::*;
 
-use crate::player::Player;
-
-#[derive(Component, Clone)]
-pub struct Gun {
-    texture: Handle<Image>,
-    pub bullet_offset: Vec2,
+use crate::entity::gun::{Gun, GunHandling};
+use crate::input_manager::PlayerInput;
+use crate::player::{Loadout, Player};
+
+/// How each gun a loadout can give handles, by its texture
+static GUN_HANDLING: &[(&str, GunHandling)] = &[(
+    "gun1.png",
+    GunHandling {
+        recoil: 60.0,
+        kick: 4.0,
+        kick_recovery: 20.0,
+        camera_kick: 3.0,
+        min_spread: 0.01,
+        max_spread: 0.15,
+        spread_per_shot: 0.04,
+        spread_recovery: 0.3,
+        knockback: 180.0,
+    },
+)];
+
+/// Handling of the gun with the texture, guns missing from the table shoot straight and still
+pub fn gun_handling(gun: &str) -> GunHandling {
+    GUN_HANDLING
+        .iter()
+        .find(|(texture, _)| *texture == gun)
+        .map_or_else(
+            || {
+                println!("no handling for gun {}", gun);
+                GunHandling::default()
+            },
+            |(_, handling)| *handling,
+        )
 }
 
-impl Gun {
-    pub fn new(texture: Handle<Image>, bullet_offset: Vec2) -> Self {
-        Self {
-            texture,
-            bullet_offset,
-        }
-    }
-
-    pub fn spawn(&self, x: f32, y: f32, commands: &mut Commands) {
-        commands
-            .spawn_bundle(SpriteBundle {
-                texture: self.texture.clone(),
-                transform: Transform::from_xyz(x, y, 20.0),
-                ..Default::default()
-            })
-            .insert(self.clone());
-    }
+/// Gun the loadout gives players
+pub fn player_gun(asset_server: &AssetServer, loadout: &Loadout) -> Gun {
+    Gun::new(asset_server.load(&loadout.gun))
+        .with_grip(Vec2::new(0.0, -7.0))
+        // the sprite is held by its middle
+        .with_pivot(Vec2::ZERO)
+        .with_muzzle(Vec2::new(46.0, 2.0))
+        .with_handling(gun_handling(&loadout.gun))
 }
 
-pub fn gun_system(
+// gives players their gun and points it where they aim
+pub fn player_gun_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    loadout: Res<Loadout>,
 
-    player_query: Query<(&Player, &Transform), Without<Gun>>,
-    mut gun_query: Query<(&Gun, &mut Transform)>,
-
-    windows: Res<Windows>,
-    q_camera: Query<(&Camera, &GlobalTransform)>,
+    player_query: Query<(Entity, &PlayerInput, Option<&Children>), With<Player>>,
+    mut gun_query: Query<&mut Gun>,
 ) {
-    // get window
-    let window = windows.get_primary().unwrap();
-    // get camera
-    let (camera, camera_transform) = q_camera.single();
-
-    // get player
-    for (_, player_transform) in player_query.iter() {
-        // spawn gun if not already spawned
-        if gun_query.iter().count() == 0 {
-            println!("spawning gun");
+    for (player_entity, input, children) in player_query.iter() {
+        let gun_entity = children
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .find(|child| gun_query.contains(**child));
+
+        let gun_entity = match gun_entity {
+            Some(gun) => *gun,
+            None => {
+                // spawn gun if not already spawned
+                println!("spawning gun");
+                let mut gun = player_gun(&asset_server, &loadout);
+                gun.aim = input.aim;
+                gun.spawn(player_entity, &mut commands);
+                continue;
+            }
+        };
 
-            Gun::new(asset_server.load("gun1.png"), Vec2::new(46.0, 2.0)).spawn(
-                player_transform.translation.x,
-                player_transform.translation.y - 7.0,
-                &mut commands,
-            );
+        // swap the gun for the new one on the next frame
+        if loadout.is_changed() && !loadout.is_added() {
+            commands.entity(gun_entity).despawn_recursive();
+            continue;
         }
 
-        for (_, mut gun_transform) in gun_query.iter_mut() {
-            // move gun to player
-            gun_transform.translation.x = player_transform.translation.x;
-            gun_transform.translation.y = player_transform.translation.y - 7.0;
-
-            // rotate gun to mouse
-            let mouse_pos =
-                crate::input_manager::get_mouse_world_pos(window, camera, camera_transform);
-
-            let delta_x = mouse_pos.x - player_transform.translation.x;
-            let delta_y = mouse_pos.y - player_transform.translation.y;
-
-            let rotation = delta_y.atan2(delta_x);
-
-            // flip gun if mouse is on left side of player
-            if delta_x < 0.0 {
-                gun_transform.scale.y = -1.0;
-            } else {
-                gun_transform.scale.y = 1.0;
-            }
-
-            gun_transform.rotation = Quat::from_rotation_z(rotation);
+        if let Ok(mut gun) = gun_query.get_mut(gun_entity) {
+            gun.aim = input.aim;
         }
     }
 }
@@ -0,0 +1,314 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::*;
+use crate::entity::bullet::{Bullet, BulletTextures, BulletType, FlyingBullet, InactiveBullet};
+use crate::entity::enemy::Enemy;
+use crate::health::Health;
+use crate::input_manager::PlayerInput;
+use crate::movement::Knockable;
+use crate::player::Player;
+use crate::quicksave::TimerState;
+use crate::simulation::FixedTime;
+use crate::status::{StatusEffect, StatusEffectState, StatusKind, Statuses};
+
+// points along the edge of the arc hitbox
+static ARC_SEGMENTS: usize = 6;
+
+/// A player's melee swing
+#[derive(Component, Clone, Debug)]
+pub struct Melee {
+    /// reach of the swing from the centre of the player, in pixels
+    pub range: f32,
+    /// angle the swing covers, in radians
+    pub arc: f32,
+    /// how long the hitbox stays out, in seconds
+    pub duration: f32,
+    cooldown: Timer,
+    pub damage: f32,
+    /// change in speed of enemies hit, in pixels per second
+    pub knockback: f32,
+    /// lingers on enemies hit
+    pub effect: Option<StatusEffect>,
+    /// whether the swing knocks enemy bullets back as the player's
+    pub deflects_bullets: bool,
+}
+
+impl Melee {
+    pub fn new(range: f32, arc: f32, duration: f32, cooldown: f32) -> Self {
+        let mut cooldown = Timer::from_seconds(cooldown, false);
+        // ready from the start
+        cooldown.tick(cooldown.duration());
+
+        Self {
+            range,
+            arc,
+            duration,
+            cooldown,
+            damage: 1.0,
+            knockback: 0.0,
+            effect: None,
+            deflects_bullets: false,
+        }
+    }
+
+    pub fn state(&self) -> MeleeState {
+        MeleeState {
+            cooldown: TimerState::from_timer(&self.cooldown),
+        }
+    }
+
+    pub fn restore(&mut self, state: &MeleeState) {
+        self.cooldown = state.cooldown.to_timer(false);
+    }
+
+    pub fn with_damage(mut self, damage: f32) -> Self {
+        self.damage = damage;
+        self
+    }
+
+    pub fn with_knockback(mut self, knockback: f32) -> Self {
+        self.knockback = knockback;
+        self
+    }
+
+    pub fn with_effect(mut self, effect: StatusEffect) -> Self {
+        self.effect = Some(effect);
+        self
+    }
+
+    pub fn with_deflect(mut self, deflects_bullets: bool) -> Self {
+        self.deflects_bullets = deflects_bullets;
+        self
+    }
+
+    // fan from the centre of the owner to the edge of the arc, pointing right
+    fn shape(&self) -> Collider {
+        let mut points = vec![Vec2::ZERO];
+        for i in 0..=ARC_SEGMENTS {
+            let angle = -self.arc / 2.0 + self.arc * i as f32 / ARC_SEGMENTS as f32;
+            points.push(Vec2::from_angle(angle) * self.range);
+        }
+
+        Collider::convex_hull(&points).unwrap()
+    }
+}
+
+impl Default for Melee {
+    fn default() -> Self {
+        // more of a shove than a kill, a second swing or the lava finishes enemies off
+        Self::new(36.0, PI * 2.0 / 3.0, 0.15, 0.4)
+            .with_damage(0.5)
+            .with_knockback(400.0)
+            .with_effect(StatusEffect::of(StatusKind::Stun))
+            .with_deflect(true)
+    }
+}
+
+/// What's saved of a melee, the rest are its settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeleeState {
+    pub cooldown: TimerState,
+}
+
+/// Short lived arc in front of its owner, hitting everything in it once
+#[derive(Component)]
+pub struct MeleeHitbox {
+    direction: Vec2,
+    shape: Collider,
+    damage: f32,
+    knockback: f32,
+    effect: Option<StatusEffect>,
+    deflects_bullets: bool,
+    lifetime: Timer,
+    /// entities already hit by this swing
+    hit: Vec<Entity>,
+}
+
+impl MeleeHitbox {
+    fn new(melee: &Melee, direction: Vec2) -> Self {
+        Self {
+            direction,
+            shape: melee.shape(),
+            damage: melee.damage,
+            knockback: melee.knockback,
+            effect: melee.effect.clone(),
+            deflects_bullets: melee.deflects_bullets,
+            lifetime: Timer::from_seconds(melee.duration, false),
+            hit: Vec::new(),
+        }
+    }
+
+    /// What's saved of the swing, without the entities it hit, they're spawned again on load
+    pub fn state(&self) -> MeleeHitboxState {
+        MeleeHitboxState {
+            direction: self.direction,
+            damage: self.damage,
+            knockback: self.knockback,
+            effect: self.effect.as_ref().map(StatusEffect::state),
+            deflects_bullets: self.deflects_bullets,
+            lifetime: TimerState::from_timer(&self.lifetime),
+        }
+    }
+
+    /// A swing of the melee brought back, having already hit `hit`
+    pub fn from_state(state: &MeleeHitboxState, melee: &Melee, hit: Vec<Entity>) -> Self {
+        Self {
+            direction: state.direction,
+            shape: melee.shape(),
+            damage: state.damage,
+            knockback: state.knockback,
+            effect: state.effect.as_ref().map(StatusEffect::from_state),
+            deflects_bullets: state.deflects_bullets,
+            lifetime: state.lifetime.to_timer(false),
+            hit,
+        }
+    }
+
+    /// Entities already hit by this swing
+    pub fn hit(&self) -> &[Entity] {
+        &self.hit
+    }
+
+    /// Spawns the swing in front of its owner
+    pub fn spawn(self, owner: Entity, melee: &Melee, commands: &mut Commands) -> Entity {
+        let angle = self.direction.y.atan2(self.direction.x);
+
+        let hitbox = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 1.0, 1.0, 0.4),
+                    custom_size: Some(Vec2::new(
+                        melee.range / 2.0,
+                        2.0 * melee.range * (melee.arc / 2.0).min(PI / 2.0).sin(),
+                    )),
+                    ..Default::default()
+                },
+                // just over the player
+                transform: Transform::from_translation(
+                    (self.direction * melee.range * 0.75).extend(1.0),
+                )
+                .with_rotation(Quat::from_rotation_z(angle)),
+                ..Default::default()
+            })
+            .insert(self)
+            .id();
+
+        commands.entity(owner).add_child(hitbox);
+        hitbox
+    }
+}
+
+/// What's saved of a swing in progress
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeleeHitboxState {
+    pub direction: Vec2,
+    pub damage: f32,
+    pub knockback: f32,
+    pub effect: Option<StatusEffectState>,
+    pub deflects_bullets: bool,
+    pub lifetime: TimerState,
+}
+
+// swings when the player asks and the last swing has cooled down
+pub fn melee_system(
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &Player, &mut Melee, &PlayerInput)>,
+    time: Res<FixedTime>,
+) {
+    for (entity, player, mut melee, input) in player_query.iter_mut() {
+        melee.cooldown.tick(time.delta());
+
+        if !input.melee || !melee.cooldown.finished() || player.dead {
+            continue;
+        }
+        melee.cooldown.reset();
+
+        // swings the way the player faces
+        MeleeHitbox::new(&melee, player.facing.to_vec2()).spawn(entity, &melee, &mut commands);
+    }
+}
+
+// hits enemies and turns enemy bullets around inside swings
+#[allow(clippy::type_complexity)]
+pub fn melee_hitbox_system(
+    mut commands: Commands,
+    mut hitbox_query: Query<(Entity, &mut MeleeHitbox, &Parent)>,
+    owner_query: Query<&Transform, (With<Player>, Without<Bullet>)>,
+    mut enemy_query: Query<
+        (&Transform, &mut Health, &mut Statuses, Knockable),
+        (With<Enemy>, Without<Bullet>),
+    >,
+    mut bullet_query: Query<FlyingBullet, Without<InactiveBullet>>,
+    (bullet_textures, rapier, time): (Res<BulletTextures>, Res<RapierContext>, Res<FixedTime>),
+) {
+    for (entity, mut hitbox, parent) in hitbox_query.iter_mut() {
+        hitbox.lifetime.tick(time.delta());
+        if hitbox.lifetime.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let owner = match owner_query.get(parent.get()) {
+            Ok(transform) => transform.translation.truncate(),
+            Err(_) => continue,
+        };
+
+        // everything the swing can touch
+        let mut filters = CollGroupsConfig::enemy().memberships;
+        if hitbox.deflects_bullets {
+            filters |= CollGroupsConfig::bullet_enemy().memberships;
+        }
+        let groups = CollisionGroups::new(CollGroupsConfig::player().memberships, filters);
+
+        let mut touched = Vec::new();
+        rapier.intersections_with_shape(
+            owner,
+            hitbox.direction.y.atan2(hitbox.direction.x),
+            &hitbox.shape,
+            QueryFilter::new().groups(groups.into()),
+            |other| {
+                touched.push(other);
+                true
+            },
+        );
+
+        for other in touched {
+            if hitbox.hit.contains(&other) {
+                continue;
+            }
+            hitbox.hit.push(other);
+
+            if let Ok((transform, mut health, mut statuses, mut knockable)) =
+                enemy_query.get_mut(other)
+            {
+                // pushed away from the player
+                let direction = (transform.translation.truncate() - owner)
+                    .try_normalize()
+                    .unwrap_or(hitbox.direction);
+                knockable.push(direction, hitbox.knockback);
+
+                health.damage(hitbox.damage);
+                if let Some(effect) = &hitbox.effect {
+                    statuses.apply(effect.clone());
+                }
+                continue;
+            }
+
+            if let Ok(mut flying) = bullet_query.get_mut(other) {
+                if flying.bullet.type_() != BulletType::Enemy {
+                    continue;
+                }
+
+                // sent back away from the player, as one of theirs
+                let direction = (flying.transform.translation.truncate() - owner)
+                    .try_normalize()
+                    .unwrap_or(hitbox.direction);
+                flying.send_back(direction, 1.0, &bullet_textures);
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input_manager::PlayerInput;
+use crate::movement::MovementController;
+use crate::player::Player;
+use crate::quicksave::TimerState;
+use crate::simulation::FixedTime;
+
+static SHIELD_COLOR: Color = Color::rgb(0.6, 0.8, 1.0);
+static PARRY_COLOR: Color = Color::rgb(1.0, 1.0, 0.6);
+
+/// A player's defence against bullets: a timed parry that sends them back,
+/// and a shield held up in front that blocks them
+#[derive(Component, Clone, Debug)]
+pub struct Shield {
+    /// how long a parry catches bullets after the press
+    parry_window: Timer,
+    parry_cooldown: Timer,
+    /// speed of parried bullets, as a multiple of their speed
+    pub parry_speed: f32,
+    /// angle the raised shield covers, in radians
+    pub arc: f32,
+    /// speed of the player with the shield up, as a multiple of their speed
+    pub slowdown: f32,
+    /// where the shield faces, set from the player's facing
+    direction: Vec2,
+    raised: bool,
+    /// color the shield gave the player, the sprite is only written when it changes
+    /// so other tints aren't painted over
+    tint: Option<Color>,
+}
+
+impl Shield {
+    pub fn new(parry_window: f32, parry_cooldown: f32) -> Self {
+        // ready from the start, and not parrying
+        let mut parry_window = Timer::from_seconds(parry_window, false);
+        parry_window.tick(parry_window.duration());
+        let mut parry_cooldown = Timer::from_seconds(parry_cooldown, false);
+        parry_cooldown.tick(parry_cooldown.duration());
+
+        Self {
+            parry_window,
+            parry_cooldown,
+            parry_speed: 1.0,
+            arc: PI,
+            slowdown: 1.0,
+            direction: Vec2::X,
+            raised: false,
+            tint: None,
+        }
+    }
+
+    pub fn with_parry_speed(mut self, parry_speed: f32) -> Self {
+        self.parry_speed = parry_speed;
+        self
+    }
+
+    pub fn with_arc(mut self, arc: f32) -> Self {
+        self.arc = arc;
+        self
+    }
+
+    pub fn with_slowdown(mut self, slowdown: f32) -> Self {
+        self.slowdown = slowdown;
+        self
+    }
+
+    pub fn state(&self) -> ShieldState {
+        ShieldState {
+            parry_window: TimerState::from_timer(&self.parry_window),
+            parry_cooldown: TimerState::from_timer(&self.parry_cooldown),
+            direction: self.direction,
+            raised: self.raised,
+        }
+    }
+
+    pub fn restore(&mut self, state: &ShieldState) {
+        self.parry_window = state.parry_window.to_timer(false);
+        self.parry_cooldown = state.parry_cooldown.to_timer(false);
+        self.direction = state.direction;
+        self.raised = state.raised;
+    }
+
+    pub fn is_parrying(&self) -> bool {
+        !self.parry_window.finished()
+    }
+
+    /// Whether the raised shield is between the player and something at this position
+    pub fn blocks(&self, player_position: Vec2, position: Vec2) -> bool {
+        let to = position - player_position;
+        self.raised && to != Vec2::ZERO && self.direction.angle_between(to).abs() <= self.arc / 2.0
+    }
+}
+
+/// What's saved of a shield, the rest are its settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShieldState {
+    pub parry_window: TimerState,
+    pub parry_cooldown: TimerState,
+    pub direction: Vec2,
+    pub raised: bool,
+}
+
+impl Default for Shield {
+    fn default() -> Self {
+        Self::new(0.15, 0.6)
+            .with_parry_speed(1.5)
+            .with_arc(PI * 2.0 / 3.0)
+            .with_slowdown(0.5)
+    }
+}
+
+// parries on a press and holds the shield up while the button is down, slowing the player
+pub fn shield_system(
+    mut player_query: Query<(
+        &Player,
+        &mut Shield,
+        &PlayerInput,
+        &mut MovementController,
+        &mut TextureAtlasSprite,
+    )>,
+    time: Res<FixedTime>,
+) {
+    for (player, mut shield, input, mut controller, mut sprite) in player_query.iter_mut() {
+        shield.parry_window.tick(time.delta());
+        shield.parry_cooldown.tick(time.delta());
+
+        // the dead don't defend themselves
+        if input.parry && shield.parry_cooldown.finished() && !player.dead {
+            shield.parry_window.reset();
+            shield.parry_cooldown.reset();
+        }
+
+        shield.raised = input.shield && !player.dead;
+        shield.direction = player.facing.to_vec2();
+
+        if shield.raised {
+            controller.input *= shield.slowdown;
+        }
+
+        let tint = match (shield.is_parrying(), shield.raised) {
+            (true, _) => Some(PARRY_COLOR),
+            (false, true) => Some(SHIELD_COLOR),
+            (false, false) => None,
+        };
+        if shield.tint != tint {
+            shield.tint = tint;
+            sprite.color = tint.unwrap_or(Color::WHITE);
+        }
+    }
+}
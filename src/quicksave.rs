@@ -0,0 +1,459 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::entity::bullet::{Bullet, BulletPool, BulletState, BulletTextures, InactiveBullet};
+use crate::entity::enemy::{Enemy, EnemyBundle, EnemyState};
+use crate::entity::gun::{Gun, GunState};
+use crate::health::Health;
+use crate::level_manager::level_of;
+use crate::movement::MovementController;
+use crate::player::melee::{Melee, MeleeHitbox, MeleeHitboxState, MeleeState};
+use crate::player::shield::{Shield, ShieldState};
+use crate::player::{Player, PlayerState};
+use crate::replay::not_replaying;
+use crate::save::save_dir;
+use crate::simulation::{FixedTime, GameRng, StepSystemAppExt};
+use crate::status::{StatusEffectState, Statuses};
+use crate::PostPhysicsStage;
+
+/// Version of the snapshot format, old quicksaves are refused instead of migrated
+pub static SNAPSHOT_VERSION: u32 = 4;
+
+/// Serialisable timer, bevy's `Timer` can't be saved directly
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimerState {
+    pub elapsed: f32,
+    pub duration: f32,
+}
+
+impl TimerState {
+    pub fn from_timer(timer: &Timer) -> Self {
+        Self {
+            elapsed: timer.elapsed_secs(),
+            duration: timer.duration().as_secs_f32(),
+        }
+    }
+
+    pub fn to_timer(&self, repeating: bool) -> Timer {
+        let mut timer = Timer::from_seconds(self.duration, repeating);
+        // ticking instead of setting the elapsed time keeps `finished` right
+        timer.tick(Duration::from_secs_f32(self.elapsed));
+        timer
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    /// memberships and filters, they change while dashing
+    pub collision_groups: (u32, u32),
+    pub movement_lock: f32,
+    pub player: PlayerState,
+    pub health: Health,
+    pub statuses: Vec<StatusEffectState>,
+    pub melee: MeleeState,
+    /// swings still out
+    pub swings: Vec<SwingSnapshot>,
+    pub shield: ShieldState,
+    /// `None` until the player has been given their gun
+    pub gun: Option<GunState>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SwingSnapshot {
+    pub hitbox: MeleeHitboxState,
+    /// enemies and bullets the swing already hit, as indices into the snapshot's lists
+    pub hit_enemies: Vec<usize>,
+    pub hit_bullets: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnemySnapshot {
+    /// iid of the level the enemy was spawned in
+    pub level_iid: Option<String>,
+    pub translation: Vec3,
+    pub enemy: EnemyState,
+    pub health: Health,
+    pub statuses: Vec<StatusEffectState>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BulletSnapshot {
+    pub bullet: BulletState,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+/// The whole gameplay state. Physics is restored through positions and velocities,
+/// contacts are found again on the next step
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub players: Vec<PlayerSnapshot>,
+    pub enemies: Vec<EnemySnapshot>,
+    pub bullets: Vec<BulletSnapshot>,
+    /// so the game draws the same random numbers after a quickload
+    pub rng: GameRng,
+    /// steps run when the game was saved
+    pub steps: u64,
+}
+
+pub struct QuicksaveEvent;
+pub struct QuickloadEvent;
+
+// snapshot read but not yet loaded, it's loaded at the end of the next step
+#[derive(Default)]
+struct PendingQuickload(Option<Snapshot>);
+
+fn quicksave_path() -> Option<PathBuf> {
+    Some(save_dir()?.join("quicksave.ron"))
+}
+
+fn write_snapshot(snapshot: &Snapshot) -> Result<PathBuf, String> {
+    let path = quicksave_path().ok_or("no user data directory")?;
+    let text = ron::ser::to_string_pretty(snapshot, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, text).map_err(|e| e.to_string())?;
+
+    Ok(path)
+}
+
+fn read_snapshot() -> Result<Snapshot, String> {
+    let path = quicksave_path().ok_or("no user data directory")?;
+    let text = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let snapshot: Snapshot = ron::from_str(&text).map_err(|e| e.to_string())?;
+
+    match snapshot.version {
+        v if v == SNAPSHOT_VERSION => Ok(snapshot),
+        v => Err(format!("quicksave version {} is not supported", v)),
+    }
+}
+
+fn quicksave_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut quicksave_events: EventWriter<QuicksaveEvent>,
+    mut quickload_events: EventWriter<QuickloadEvent>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        quicksave_events.send(QuicksaveEvent);
+    }
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        quickload_events.send(QuickloadEvent);
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn quicksave_system(
+    mut quicksave_events: EventReader<QuicksaveEvent>,
+    player_query: Query<(
+        (&Player, &Transform, &Velocity),
+        (&CollisionGroups, &MovementController),
+        (&Health, &Statuses),
+        (&Melee, &Shield, Option<&Children>),
+    )>,
+    enemy_query: Query<(Entity, &Enemy, &GlobalTransform, &Health, &Statuses)>,
+    bullet_query: Query<(Entity, &Bullet, &Transform, &Velocity), Without<InactiveBullet>>,
+    (hitbox_query, gun_query): (Query<&MeleeHitbox>, Query<&Gun>),
+    parent_query: Query<&Parent>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    (levels, rng, time): (Res<Assets<LdtkLevel>>, Res<GameRng>, Res<FixedTime>),
+) {
+    if quicksave_events.iter().count() == 0 {
+        return;
+    }
+
+    let enemies: Vec<_> = enemy_query.iter().collect();
+    let bullets: Vec<_> = bullet_query.iter().collect();
+
+    // what a swing hit is saved by its place in the lists, the entities are new after loading
+    let swing = |hitbox: &MeleeHitbox| SwingSnapshot {
+        hitbox: hitbox.state(),
+        hit_enemies: hitbox
+            .hit()
+            .iter()
+            .filter_map(|hit| enemies.iter().position(|(entity, ..)| entity == hit))
+            .collect(),
+        hit_bullets: hitbox
+            .hit()
+            .iter()
+            .filter_map(|hit| bullets.iter().position(|(entity, ..)| entity == hit))
+            .collect(),
+    };
+
+    // in player order, so each is loaded back into the same player
+    let mut players: Vec<_> = player_query.iter().collect();
+    players.sort_by_key(|((player, ..), ..)| player.id);
+
+    let players = players
+        .into_iter()
+        .map(
+            |(
+                (player, transform, velocity),
+                (groups, controller),
+                (health, statuses),
+                (melee, shield, children),
+            )| {
+                let children = children.into_iter().flat_map(|children| children.iter());
+
+                PlayerSnapshot {
+                    position: transform.translation.truncate(),
+                    velocity: velocity.linvel,
+                    collision_groups: (groups.memberships, groups.filters),
+                    movement_lock: controller.lock_remaining(),
+                    player: player.state(),
+                    health: *health,
+                    statuses: statuses.state(),
+                    melee: melee.state(),
+                    swings: children
+                        .clone()
+                        .filter_map(|child| hitbox_query.get(*child).ok())
+                        .map(swing)
+                        .collect(),
+                    shield: shield.state(),
+                    gun: children
+                        .filter_map(|child| gun_query.get(*child).ok())
+                        .map(Gun::state)
+                        .next(),
+                }
+            },
+        )
+        .collect();
+
+    let enemies = enemies
+        .iter()
+        .map(
+            |(entity, enemy, transform, health, statuses)| EnemySnapshot {
+                level_iid: level_of(*entity, &parent_query)
+                    .and_then(|level| level_query.get(level).ok())
+                    .and_then(|handle| levels.get(handle))
+                    .map(|level| level.level.iid.clone()),
+                translation: transform.translation(),
+                enemy: enemy.state(),
+                health: **health,
+                statuses: statuses.state(),
+            },
+        )
+        .collect();
+
+    let bullets = bullets
+        .iter()
+        .map(|(_, bullet, transform, velocity)| BulletSnapshot {
+            bullet: bullet.state(),
+            position: transform.translation.truncate(),
+            velocity: velocity.linvel,
+        })
+        .collect();
+
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        players,
+        enemies,
+        bullets,
+        rng: rng.clone(),
+        steps: time.steps,
+    };
+
+    match write_snapshot(&snapshot) {
+        Ok(path) => println!("Quicksaved to {}.", path.display()),
+        Err(e) => println!("could not quicksave: {}", e),
+    }
+}
+
+fn quickload_system(
+    mut quickload_events: EventReader<QuickloadEvent>,
+    mut pending: ResMut<PendingQuickload>,
+) {
+    if quickload_events.iter().count() == 0 {
+        return;
+    }
+
+    match read_snapshot() {
+        Ok(snapshot) => pending.0 = Some(snapshot),
+        Err(e) => println!("could not quickload: {}", e),
+    }
+}
+
+// loads the snapshot at the end of a step, so the next step starts from it like it did when saved
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn apply_quickload_system(
+    mut commands: Commands,
+    mut pending: ResMut<PendingQuickload>,
+    mut player_query: Query<(
+        (Entity, &mut Player, &mut Transform, &mut Velocity),
+        (&mut CollisionGroups, &mut MovementController),
+        (&mut Health, &mut Statuses),
+        (&mut Melee, &mut Shield, Option<&Children>),
+    )>,
+    enemy_query: Query<Entity, With<Enemy>>,
+    bullet_query: Query<Entity, (With<Bullet>, Without<InactiveBullet>)>,
+    (hitbox_query, mut gun_query): (Query<Entity, With<MeleeHitbox>>, Query<&mut Gun>),
+    level_query: Query<(Entity, &Handle<LdtkLevel>, &GlobalTransform)>,
+    (levels, asset_server, bullet_textures, mut bullet_pool): (
+        Res<Assets<LdtkLevel>>,
+        Res<AssetServer>,
+        Res<BulletTextures>,
+        ResMut<BulletPool>,
+    ),
+    (mut rng, mut time, mut collision_events): (
+        ResMut<GameRng>,
+        ResMut<FixedTime>,
+        ResMut<Events<CollisionEvent>>,
+    ),
+) {
+    let snapshot = match pending.0.take() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+
+    // enemies are spawned again, killed ones come back
+    for entity in enemy_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let enemies: Vec<_> = snapshot
+        .enemies
+        .iter()
+        .map(|saved| {
+            let level = level_query.iter().find(|(_, handle, _)| {
+                levels
+                    .get(*handle)
+                    .map(|level| Some(&level.level.iid) == saved.level_iid.as_ref())
+                    .unwrap_or(false)
+            });
+
+            // spawned in their level, so they go away with it
+            let translation = match level {
+                Some((_, _, level_transform)) => saved.translation - level_transform.translation(),
+                None => saved.translation,
+            };
+
+            let enemy = commands
+                .spawn_bundle(EnemyBundle::new(
+                    Enemy::from_state(&saved.enemy),
+                    &asset_server,
+                ))
+                .insert(Transform::from_translation(translation))
+                .insert(saved.health)
+                .insert(Statuses::from_state(&saved.statuses))
+                .id();
+
+            if let Some((level_entity, _, _)) = level {
+                commands.entity(level_entity).add_child(enemy);
+            }
+            enemy
+        })
+        .collect();
+
+    for entity in bullet_query.iter() {
+        bullet_pool.release(entity, &mut commands);
+    }
+    let bullets: Vec<_> = snapshot
+        .bullets
+        .iter()
+        .map(|saved| {
+            let bullet = Bullet::from_state(&saved.bullet, &bullet_textures).spawn(
+                saved.position.x,
+                saved.position.y,
+                saved.bullet.direction,
+                &mut bullet_pool,
+                &mut commands,
+            );
+
+            // keep the exact velocity, a bullet knocked about doesn't fly at its speed
+            commands
+                .entity(bullet)
+                .insert(Velocity::linear(saved.velocity));
+            bullet
+        })
+        .collect();
+
+    // swings come back with the rest
+    for entity in hitbox_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // players keep their entities
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|((_, player, ..), ..)| player.id);
+
+    for (
+        (
+            (entity, mut player, mut transform, mut velocity),
+            (mut groups, mut controller),
+            (mut health, mut statuses),
+            (mut melee, mut shield, children),
+        ),
+        saved,
+    ) in players.into_iter().zip(snapshot.players.iter())
+    {
+        transform.translation.x = saved.position.x;
+        transform.translation.y = saved.position.y;
+        velocity.linvel = saved.velocity;
+        velocity.angvel = 0.0;
+        *groups = CollisionGroups::new(saved.collision_groups.0, saved.collision_groups.1);
+        controller.lock(saved.movement_lock);
+        player.restore(&saved.player);
+        *health = saved.health;
+        *statuses = Statuses::from_state(&saved.statuses);
+        melee.restore(&saved.melee);
+        shield.restore(&saved.shield);
+
+        for swing in saved.swings.iter() {
+            let hit = swing
+                .hit_enemies
+                .iter()
+                .filter_map(|i| enemies.get(*i))
+                .chain(swing.hit_bullets.iter().filter_map(|i| bullets.get(*i)))
+                .copied()
+                .collect();
+            MeleeHitbox::from_state(&swing.hitbox, &melee, hit).spawn(
+                entity,
+                &melee,
+                &mut commands,
+            );
+        }
+
+        let gun = children
+            .into_iter()
+            .flat_map(|children| children.iter())
+            .find(|child| gun_query.contains(**child));
+        if let (Some(gun), Some(saved)) = (gun, &saved.gun) {
+            gun_query.get_mut(*gun).unwrap().restore(saved);
+        }
+    }
+
+    *rng = snapshot.rng.clone();
+    time.steps = snapshot.steps;
+    // contacts of the world before the load
+    collision_events.clear();
+
+    println!("Quickloaded.");
+}
+
+pub struct QuicksavePlugin;
+
+impl Plugin for QuicksavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<QuicksaveEvent>()
+            .add_event::<QuickloadEvent>()
+            .add_system(
+                quicksave_input_system
+                    .with_run_criteria(not_replaying)
+                    .before(quicksave_system),
+            )
+            .init_resource::<PendingQuickload>()
+            .add_system(quicksave_system.before(quickload_system))
+            .add_system(quickload_system)
+            .add_step_system_to_stage(PostPhysicsStage, apply_quickload_system);
+    }
+}
@@ -0,0 +1,367 @@
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::app::AppExit;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::bytes::{write_facing_mode, write_input, Reader};
+use crate::config::FacingMode;
+use crate::input_manager::{input_system, InputSystem, PlayerInput};
+use crate::level_manager::level_at;
+use crate::player::Player;
+use crate::save::SavingEnabled;
+use crate::simulation::StepSystemAppExt;
+use crate::GameplayStage;
+
+static REPLAY_MAGIC: &[u8; 4] = b"ASRP";
+// 2 added the melee, parry and shield buttons to the inputs, and the facing mode
+static REPLAY_VERSION: u8 = 2;
+// the recording is written this often, in steps, so a crash doesn't lose it
+static WRITE_INTERVAL: usize = 600;
+
+/// Inputs of every player for every gameplay step, with what's needed to play them back
+#[derive(Clone, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    /// iid of the level the recording started in
+    pub level_iid: String,
+    /// facing mode setting of the run, it changes where players dash and swing
+    pub facing_mode: FacingMode,
+    /// one entry per step, with the input of each player
+    pub steps: Vec<Vec<PlayerInput>>,
+}
+
+impl Replay {
+    /// Compact binary form: a header, then runs of identical steps stored once with their length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&(self.level_iid.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.level_iid.as_bytes());
+        write_facing_mode(&mut bytes, self.facing_mode);
+
+        let mut runs: Vec<(u16, &Vec<PlayerInput>)> = Vec::new();
+        for step in self.steps.iter() {
+            match runs.last_mut() {
+                Some((count, inputs)) if *inputs == step && *count < u16::MAX => *count += 1,
+                _ => runs.push((1, step)),
+            }
+        }
+
+        bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (count, inputs) in runs {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.push(inputs.len() as u8);
+
+            for input in inputs {
+                write_input(&mut bytes, input);
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(4)? != REPLAY_MAGIC {
+            return Err("not a replay".to_string());
+        }
+        let version = reader.u8()?;
+        if version != REPLAY_VERSION {
+            return Err(format!("replay version {} is not supported", version));
+        }
+
+        let seed = reader.u64()?;
+        let iid_len = reader.u16()? as usize;
+        let level_iid = String::from_utf8(reader.take(iid_len)?.to_vec())
+            .map_err(|_| "level iid is not text".to_string())?;
+        let facing_mode = reader.facing_mode()?;
+
+        let mut steps = Vec::new();
+        for _ in 0..reader.u32()? {
+            let count = reader.u16()?;
+            let mut inputs = Vec::new();
+
+            for _ in 0..reader.u8()? {
+                inputs.push(reader.input()?);
+            }
+
+            steps.extend(std::iter::repeat_n(inputs, count as usize));
+        }
+
+        Ok(Self {
+            seed,
+            level_iid,
+            facing_mode,
+            steps,
+        })
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        Self::from_bytes(&fs::read(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+/// Records the inputs of the run to a file
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+/// Feeds the inputs of a replay to the players instead of the keyboard and mouse
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+}
+
+/// Run criteria for what the recording doesn't capture, like restarting, quickloading and
+/// players joining, which would make a replay play out differently than it was recorded
+pub fn not_replaying(
+    recorder: Option<Res<Recorder>>,
+    playback: Option<Res<Playback>>,
+) -> ShouldRun {
+    let playing = playback.is_some_and(|playback| playback.next < playback.replay.steps.len());
+
+    match recorder.is_none() && !playing {
+        true => ShouldRun::Yes,
+        false => ShouldRun::No,
+    }
+}
+
+// the level the players are in, used to tell which level a replay starts in
+fn players_level(
+    player_query: &Query<&GlobalTransform, With<Player>>,
+    level_query: &Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: &Assets<LdtkLevel>,
+) -> String {
+    player_query
+        .iter()
+        .next()
+        .and_then(|transform| level_at(level_query, levels, transform.translation().truncate()))
+        .map(|level| level.iid.clone())
+        .unwrap_or_default()
+}
+
+fn playback_system(
+    mut playback: ResMut<Playback>,
+    mut input_query: Query<(&Player, &mut PlayerInput)>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    level_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    // steps before the players are spawned aren't recorded
+    if input_query.is_empty() {
+        return;
+    }
+
+    if playback.next == 0 {
+        let level_iid = players_level(&player_query, &level_query, &levels);
+        if level_iid != playback.replay.level_iid {
+            println!(
+                "replay was recorded in level {}, not {}",
+                playback.replay.level_iid, level_iid
+            );
+        }
+    }
+
+    let inputs = match playback.replay.steps.get(playback.next) {
+        Some(inputs) => inputs,
+        None => return,
+    };
+
+    // inputs are stored in player order
+    let mut players: Vec<_> = input_query.iter_mut().collect();
+    players.sort_by_key(|(player, _)| player.id);
+
+    for ((_, mut input), replayed) in players.into_iter().zip(inputs.iter()) {
+        *input = *replayed;
+    }
+
+    playback.next += 1;
+    if playback.next == playback.replay.steps.len() {
+        println!("Replay finished, back to live input.");
+    }
+}
+
+fn record_system(
+    mut recorder: ResMut<Recorder>,
+    input_query: Query<(&Player, &PlayerInput)>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    level_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    if input_query.is_empty() {
+        return;
+    }
+
+    if recorder.replay.steps.is_empty() {
+        recorder.replay.level_iid = players_level(&player_query, &level_query, &levels);
+    }
+
+    let mut players: Vec<_> = input_query.iter().collect();
+    players.sort_by_key(|(player, _)| player.id);
+    recorder
+        .replay
+        .steps
+        .push(players.into_iter().map(|(_, input)| *input).collect());
+
+    if recorder.replay.steps.len().is_multiple_of(WRITE_INTERVAL) {
+        write_recording(&recorder);
+    }
+}
+
+fn write_recording(recorder: &Recorder) {
+    if let Err(e) = recorder.replay.save(&recorder.path) {
+        println!("could not write replay {}: {}", recorder.path.display(), e);
+    }
+}
+
+fn record_exit_system(recorder: Res<Recorder>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.iter().next().is_some() {
+        write_recording(&recorder);
+        println!(
+            "Recorded {} steps to {}.",
+            recorder.replay.steps.len(),
+            recorder.path.display()
+        );
+    }
+}
+
+pub enum ReplayMode {
+    Off,
+    Record {
+        path: PathBuf,
+        seed: u64,
+        facing_mode: FacingMode,
+    },
+    Play(Replay),
+}
+
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let recording = match &self.mode {
+            ReplayMode::Off => return,
+            ReplayMode::Record {
+                path,
+                seed,
+                facing_mode,
+            } => {
+                app.insert_resource(Recorder {
+                    path: path.clone(),
+                    replay: Replay {
+                        seed: *seed,
+                        facing_mode: *facing_mode,
+                        ..Default::default()
+                    },
+                })
+                .add_step_system_to_stage(
+                    GameplayStage,
+                    record_system.label(InputSystem).after(input_system),
+                )
+                .add_system_to_stage(CoreStage::Last, record_exit_system);
+                true
+            }
+            ReplayMode::Play(replay) => {
+                app.insert_resource(Playback {
+                    replay: replay.clone(),
+                    next: 0,
+                })
+                .add_step_system_to_stage(
+                    GameplayStage,
+                    playback_system.label(InputSystem).after(input_system),
+                );
+                false
+            }
+        };
+
+        // a save would put the player somewhere else than where the recording started
+        println!(
+            "{}, saving, restarting, quickloading and joining are off.",
+            match recording {
+                true => "Recording",
+                false => "Playing a replay",
+            }
+        );
+        app.insert_resource(SavingEnabled(false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(step: usize, player: usize) -> PlayerInput {
+        PlayerInput {
+            movement: Vec2::new(0.6, -0.8),
+            aim: Vec2::new(step as f32, player as f32),
+            fire: step.is_multiple_of(3),
+            shield: player == 1,
+            ..Default::default()
+        }
+    }
+
+    fn replay(steps: Vec<Vec<PlayerInput>>) -> Replay {
+        Replay {
+            seed: 0x1234_5678_9abc_def0,
+            level_iid: "28c31d50-2a00-11ed-9c42-3190aff26295".to_string(),
+            facing_mode: FacingMode::Blend { aim_weight: 0.25 },
+            steps,
+        }
+    }
+
+    fn assert_round_trips(replay: &Replay) {
+        let read = Replay::from_bytes(&replay.to_bytes()).unwrap();
+
+        assert_eq!(read.seed, replay.seed);
+        assert_eq!(read.level_iid, replay.level_iid);
+        assert_eq!(read.facing_mode, replay.facing_mode);
+        assert_eq!(read.steps, replay.steps);
+    }
+
+    #[test]
+    fn players_joining_and_leaving_round_trip() {
+        // one player, a second one joins, then both leave
+        let steps = (0..30)
+            .map(|step| match step {
+                0..=9 => vec![input(step, 0)],
+                10..=19 => vec![input(step, 0), input(step, 1)],
+                _ => Vec::new(),
+            })
+            .collect();
+
+        assert_round_trips(&replay(steps));
+    }
+
+    #[test]
+    fn long_runs_of_the_same_input_round_trip() {
+        // more identical steps than one run can count
+        let mut steps = vec![vec![input(1, 0)]; u16::MAX as usize * 2 + 10];
+        steps.push(vec![input(2, 0)]);
+
+        let replay = replay(steps);
+        assert_round_trips(&replay);
+        assert!(replay.to_bytes().len() < 200);
+    }
+
+    #[test]
+    fn cut_off_replays_are_refused() {
+        let bytes = replay(vec![vec![input(0, 0), input(0, 1)]; 5]).to_bytes();
+
+        for len in 0..bytes.len() {
+            assert!(Replay::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use ron::Value;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::{PlayerRespawnEvent, RespawnPoint};
+use crate::feedback::FeedbackEvent;
+use crate::level_manager::level_at;
+use crate::player::{Loadout, Player};
+
+/// Version of the save format, bump it and add a step to `migrate` when `Progress` changes
+pub static SAVE_VERSION: u32 = 1;
+pub static SAVE_SLOTS: usize = 3;
+// folder in the user data directory
+static SAVE_DIR: &str = "astro_bevy";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub deaths: u32,
+    pub enemies_killed: u32,
+    pub shots_fired: u32,
+    pub play_time: Duration,
+}
+
+/// Everything kept between runs, missing fields start from scratch
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Progress {
+    /// iids of the levels the player has been to
+    pub unlocked_levels: BTreeSet<String>,
+    pub current_level: Option<String>,
+    pub checkpoint: Option<RespawnPoint>,
+    pub loadout: Loadout,
+    pub stats: Stats,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile<T> {
+    version: u32,
+    progress: T,
+}
+
+/// Save slot the progress is saved to and loaded from,
+/// `None` after a slot couldn't be read, so it isn't overwritten
+pub struct ActiveSlot(pub Option<usize>);
+
+impl Default for ActiveSlot {
+    fn default() -> Self {
+        Self(Some(1))
+    }
+}
+
+/// Whether progress is saved and loaded at all, replays and network clients turn it off
+pub struct SavingEnabled(pub bool);
+
+impl Default for SavingEnabled {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Saves the progress to the active slot
+pub struct SaveEvent;
+
+/// Makes the slot active and loads its progress, a slot without a save starts from scratch
+pub struct LoadEvent {
+    pub slot: usize,
+}
+
+// progress loaded but not yet given to the player
+#[derive(Default)]
+struct PendingLoad(bool);
+
+/// Folder the game saves to, `None` on platforms without a user data directory
+pub fn save_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join(SAVE_DIR))
+}
+
+fn slot_path(slot: usize) -> Option<PathBuf> {
+    Some(save_dir()?.join(format!("slot_{}.ron", slot)))
+}
+
+// brings progress saved by older versions up to date, one version at a time
+fn migrate(version: u32, progress: Value) -> Result<Value, String> {
+    match version {
+        v if v == SAVE_VERSION => Ok(progress),
+        v if v > SAVE_VERSION => Err(format!("save version {} is newer than the game", v)),
+        v => Err(format!("no migration from save version {}", v)),
+    }
+}
+
+fn progress_to_string(progress: &Progress) -> Result<String, String> {
+    let file = SaveFile {
+        version: SAVE_VERSION,
+        progress,
+    };
+    ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string())
+}
+
+fn progress_from_str(text: &str) -> Result<Progress, String> {
+    // read the progress loosely first, its format depends on the version
+    let file: SaveFile<Value> = ron::from_str(text).map_err(|e| e.to_string())?;
+    migrate(file.version, file.progress)?
+        .into_rust()
+        .map_err(|e| e.to_string())
+}
+
+pub fn save_progress(slot: usize, progress: &Progress) -> Result<(), String> {
+    let path = slot_path(slot).ok_or("no user data directory")?;
+    let text = progress_to_string(progress)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, text).map_err(|e| e.to_string())
+}
+
+/// Progress saved in the slot, `None` if the slot is empty
+pub fn load_progress(slot: usize) -> Result<Option<Progress>, String> {
+    let path = slot_path(slot).ok_or("no user data directory")?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    progress_from_str(&text).map(Some)
+}
+
+fn load_active_slot(
+    slot: Res<ActiveSlot>,
+    saving: Res<SavingEnabled>,
+    mut load_events: EventWriter<LoadEvent>,
+) {
+    if let (true, Some(slot)) = (saving.0, slot.0) {
+        load_events.send(LoadEvent { slot });
+    }
+}
+
+fn save_input_system(keyboard_input: Res<Input<KeyCode>>, mut load_events: EventWriter<LoadEvent>) {
+    // F1, F2 and F3 switch slots
+    let keys = [KeyCode::F1, KeyCode::F2, KeyCode::F3];
+    for (i, key) in keys.iter().enumerate().take(SAVE_SLOTS) {
+        if keyboard_input.just_pressed(*key) {
+            load_events.send(LoadEvent { slot: i + 1 });
+        }
+    }
+}
+
+// keeps the progress up to date with the game, saving on checkpoints and new levels
+fn progress_system(
+    mut progress: ResMut<Progress>,
+    loadout: Res<Loadout>,
+    pending: Res<PendingLoad>,
+    player_query: Query<(&Player, &GlobalTransform)>,
+    level_query: Query<(&Handle<LdtkLevel>, &GlobalTransform)>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut save_events: EventWriter<SaveEvent>,
+) {
+    // don't overwrite what's being loaded
+    if pending.0 {
+        return;
+    }
+
+    if loadout.is_changed() && progress.loadout != *loadout {
+        progress.loadout = loadout.clone();
+    }
+
+    // progress follows the first player
+    let (player, transform) = match player_query.iter().find(|(player, _)| player.id == 0) {
+        Some(player) => player,
+        None => return,
+    };
+
+    let mut changed = false;
+
+    if let Some(level) = level_at(&level_query, &levels, transform.translation().truncate()) {
+        if progress.current_level.as_ref() != Some(&level.iid) {
+            progress.current_level = Some(level.iid.clone());
+            progress.unlocked_levels.insert(level.iid.clone());
+            changed = true;
+        }
+    }
+
+    if player.respawn_point().is_some() && progress.checkpoint.as_ref() != player.respawn_point() {
+        progress.checkpoint = player.respawn_point().cloned();
+        changed = true;
+    }
+
+    if changed {
+        save_events.send(SaveEvent);
+    }
+}
+
+fn stats_system(
+    mut progress: ResMut<Progress>,
+    mut feedback_events: EventReader<FeedbackEvent>,
+    mut respawn_events: EventReader<PlayerRespawnEvent>,
+    time: Res<Time>,
+) {
+    let stats = &mut progress.stats;
+
+    for event in feedback_events.iter() {
+        match event {
+            FeedbackEvent::PlayerShot => stats.shots_fired += 1,
+            FeedbackEvent::EnemyKilled => stats.enemies_killed += 1,
+            FeedbackEvent::PlayerHit | FeedbackEvent::Parried => {}
+        }
+    }
+    stats.deaths += respawn_events.iter().count() as u32;
+    stats.play_time += time.delta();
+}
+
+fn save_system(
+    mut save_events: EventReader<SaveEvent>,
+    progress: Res<Progress>,
+    slot: Res<ActiveSlot>,
+    saving: Res<SavingEnabled>,
+) {
+    // saving once is enough
+    if save_events.iter().count() == 0 || !saving.0 {
+        return;
+    }
+
+    let slot = match slot.0 {
+        Some(slot) => slot,
+        None => return,
+    };
+
+    match save_progress(slot, &progress) {
+        Ok(()) => println!("Saved to slot {}.", slot),
+        Err(e) => println!("could not save to slot {}: {}", slot, e),
+    }
+}
+
+fn load_system(
+    mut load_events: EventReader<LoadEvent>,
+    mut current: ResMut<Progress>,
+    mut loadout: ResMut<Loadout>,
+    mut slot: ResMut<ActiveSlot>,
+    mut pending: ResMut<PendingLoad>,
+    saving: Res<SavingEnabled>,
+) {
+    let event = match load_events.iter().last() {
+        Some(event) => event,
+        None => return,
+    };
+
+    // switching slots would move the player, and save over the slot later
+    if !saving.0 {
+        println!("Saving is off, not loading slot {}.", event.slot);
+        return;
+    }
+
+    let progress = match load_progress(event.slot) {
+        Ok(Some(progress)) => {
+            println!("Loaded slot {}.", event.slot);
+            progress
+        }
+        Ok(None) => {
+            println!("Slot {} is empty, starting over.", event.slot);
+            Progress::default()
+        }
+        Err(e) => {
+            // keep playing, without overwriting a save we can't read
+            println!("could not load slot {}, saving is off: {}", event.slot, e);
+            slot.0 = None;
+            return;
+        }
+    };
+
+    slot.0 = Some(event.slot);
+    // a changed loadout gives the players new guns
+    if *loadout != progress.loadout {
+        *loadout = progress.loadout.clone();
+    }
+    *current = progress;
+    pending.0 = true;
+}
+
+// puts the player back where the loaded progress left them, once the player is spawned
+fn apply_load_system(
+    mut pending: ResMut<PendingLoad>,
+    progress: Res<Progress>,
+    mut player_query: Query<(&mut Player, &mut Transform, &mut Velocity)>,
+    mut level_set_query: Query<&mut LevelSet>,
+) {
+    if !pending.0 {
+        return;
+    }
+
+    let (mut player, mut transform, mut velocity) = match player_query
+        .iter_mut()
+        .find(|(player, _, _)| player.id == 0)
+    {
+        Some(player) => player,
+        None => return,
+    };
+
+    player.set_respawn_point(progress.checkpoint.clone());
+    let position = player.respawn_position();
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+    velocity.linvel = Vec2::ZERO;
+
+    for mut level_set in level_set_query.iter_mut() {
+        for iid in progress.unlocked_levels.iter() {
+            if !level_set.iids.contains(iid) {
+                level_set.iids.insert(iid.clone());
+            }
+        }
+    }
+
+    pending.0 = false;
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveEvent>()
+            .add_event::<LoadEvent>()
+            .init_resource::<Progress>()
+            .init_resource::<ActiveSlot>()
+            .init_resource::<SavingEnabled>()
+            .init_resource::<PendingLoad>()
+            .add_startup_system(load_active_slot)
+            .add_system(save_input_system.before(load_system))
+            .add_system(load_system.before(apply_load_system))
+            .add_system(apply_load_system.before(progress_system))
+            .add_system(progress_system.before(save_system))
+            .add_system(stats_system)
+            .add_system(save_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::DashRules;
+
+    #[test]
+    fn missing_fields_start_from_scratch() {
+        let progress = progress_from_str("(version: 1, progress: (current_level: Some(\"a\")))");
+
+        assert_eq!(
+            progress.unwrap(),
+            Progress {
+                current_level: Some("a".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn missing_dash_rules_are_on() {
+        let progress = progress_from_str(
+            "(version: 1, progress: (loadout: (gun: \"gun1.png\", dash_charges: 2, \
+             dash_rules: (through_enemies: false))))",
+        )
+        .unwrap();
+
+        assert_eq!(
+            progress.loadout.dash_rules,
+            DashRules {
+                through_enemies: false,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let mut progress = Progress {
+            current_level: Some("28c31d50-2a00-11ed-9c42-3190aff26295".to_string()),
+            checkpoint: Some(RespawnPoint {
+                position: Vec2::new(120.0, 64.0),
+                level_iid: Some("28c31d50-2a00-11ed-9c42-3190aff26295".to_string()),
+            }),
+            ..Default::default()
+        };
+        progress
+            .unlocked_levels
+            .insert("d10a4e50-2a00-11ed-8634-bb1f07d1b82b".to_string());
+        progress.loadout.dash_charges = 3;
+        progress.loadout.dash_rules.through_hazards = false;
+        progress.stats.deaths = 4;
+        progress.stats.play_time = Duration::from_secs_f64(90.25);
+
+        let text = progress_to_string(&progress).unwrap();
+        assert_eq!(progress_from_str(&text).unwrap(), progress);
+    }
+
+    #[test]
+    fn newer_saves_are_refused() {
+        assert!(progress_from_str("(version: 2, progress: ())").is_err());
+    }
+}
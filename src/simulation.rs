@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use bevy::ecs::schedule::{IntoSystemDescriptor, ShouldRun};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::FIXED_TIMESTEP;
+use crate::feedback::HitStop;
+use crate::{GameplayStage, PostPhysicsStage};
+
+// steps that can pile up while the game can't keep up, more are dropped
+const MAX_BACKLOG: f32 = 5.0;
+
+/// Clock of the fixed step, which runs gameplay and physics together in steps of the same length.
+/// As many steps run in a frame as real time allows, up to `MAX_BACKLOG` of them
+pub struct FixedTime {
+    step: f32,
+    accumulator: f32,
+    /// steps run since startup
+    pub steps: u64,
+}
+
+impl FixedTime {
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            accumulator: 0.0,
+            steps: 0,
+        }
+    }
+
+    pub fn delta(&self) -> Duration {
+        Duration::from_secs_f32(self.step)
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.step
+    }
+
+    /// Adds elapsed time, steps are taken from it until less than one is left
+    pub fn advance(&mut self, seconds: f32) {
+        self.accumulator = (self.accumulator + seconds).min(self.step * MAX_BACKLOG);
+    }
+
+    /// Adds exactly one step, for headless runs that don't care about real time
+    pub fn advance_step(&mut self) {
+        self.accumulator = self.step;
+    }
+
+    // takes a step out of the elapsed time, if there is enough of it
+    fn take_step(&mut self) -> bool {
+        if self.accumulator < self.step {
+            return false;
+        }
+
+        self.accumulator -= self.step;
+        self.steps += 1;
+        true
+    }
+}
+
+/// Schedule of a whole step: `GameplayStage`, rapier's stages and `PostPhysicsStage`,
+/// run once for every step of elapsed time before `CoreStage::Update`
+#[derive(StageLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FixedStepStage;
+
+// loops the step schedule until the elapsed time is used up
+fn fixed_step(mut fixed_time: ResMut<FixedTime>) -> ShouldRun {
+    match fixed_time.take_step() {
+        true => ShouldRun::YesAndCheckAgain,
+        false => ShouldRun::No,
+    }
+}
+
+// time stands still during hit-stop
+fn fixed_time_system(mut fixed_time: ResMut<FixedTime>, hit_stop: Res<HitStop>, time: Res<Time>) {
+    if !hit_stop.is_active() {
+        fixed_time.advance(time.delta_seconds());
+    }
+}
+
+fn headless_fixed_time_system(mut fixed_time: ResMut<FixedTime>) {
+    fixed_time.advance_step();
+}
+
+/// Adding systems to the stages of the fixed step
+pub trait StepSystemAppExt {
+    /// Like `add_system_to_stage`, for `GameplayStage` and `PostPhysicsStage`
+    fn add_step_system_to_stage<Params>(
+        &mut self,
+        stage_label: impl StageLabel,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+}
+
+impl StepSystemAppExt for App {
+    fn add_step_system_to_stage<Params>(
+        &mut self,
+        stage_label: impl StageLabel,
+        system: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        self.stage(FixedStepStage, |schedule: &mut Schedule| {
+            schedule.add_system_to_stage(stage_label, system)
+        })
+    }
+}
+
+/// Seeded random numbers for everything in gameplay, so runs with the same seed play out the same.
+/// Saved with quicksaves, so a quickload draws the same numbers again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameRng {
+    // the generator behind `StdRng`, named so its state can be saved
+    rng: ChaCha12Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Puts gameplay and physics on the fixed timestep, add it after the rapier plugin
+/// with its default system setup turned off
+pub struct SimulationPlugin {
+    pub seed: u64,
+    /// one step on every update instead of following real time
+    pub headless: bool,
+}
+
+// one of rapier's stages, for the step schedule
+fn physics_stage(stage: PhysicsStages) -> SystemStage {
+    SystemStage::parallel().with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(stage))
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.world
+            .resource_mut::<RapierConfiguration>()
+            .timestep_mode = TimestepMode::Fixed {
+            dt: FIXED_TIMESTEP,
+            substeps: 1,
+        };
+
+        // physics steps right after gameplay, so collision events are read on the next step,
+        // however many frames later it runs
+        let step_schedule = Schedule::default()
+            .with_run_criteria(fixed_step)
+            .with_stage(GameplayStage, SystemStage::parallel())
+            .with_stage(
+                PhysicsStages::SyncBackend,
+                physics_stage(PhysicsStages::SyncBackend),
+            )
+            .with_stage(
+                PhysicsStages::StepSimulation,
+                physics_stage(PhysicsStages::StepSimulation),
+            )
+            .with_stage(
+                PhysicsStages::Writeback,
+                physics_stage(PhysicsStages::Writeback),
+            )
+            .with_stage(PostPhysicsStage, SystemStage::parallel());
+
+        app.insert_resource(FixedTime::new(FIXED_TIMESTEP))
+            .insert_resource(GameRng::new(self.seed))
+            .add_stage_before(CoreStage::Update, FixedStepStage, step_schedule)
+            // despawns are only seen in the frame they happen
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                physics_stage(PhysicsStages::DetectDespawn),
+            );
+
+        match self.headless {
+            true => app.add_system_to_stage(CoreStage::First, headless_fixed_time_system),
+            false => app.add_system_to_stage(CoreStage::First, fixed_time_system),
+        };
+    }
+}
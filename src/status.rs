@@ -0,0 +1,350 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::entity::bullet::BulletSystem;
+use crate::health::{Health, HealthSystem};
+use crate::input_manager::{InputSystem, PlayerInput};
+use crate::movement::{movement_system, MovementController};
+use crate::player::player_system;
+use crate::player::shield::shield_system;
+use crate::quicksave::TimerState;
+use crate::simulation::{FixedTime, StepSystemAppExt};
+use crate::GameplayStage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StatusKind {
+    Burn,
+    Slow,
+    Poison,
+    Stun,
+}
+
+/// What happens when an effect is applied to an entity that already has one of its kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stacking {
+    /// the new effect replaces the old one
+    Refresh,
+    /// stacks up to this many times, each stack adding its damage, and restarts the duration
+    Stack(u32),
+    /// the new effect is dropped while the old one lasts
+    Ignore,
+}
+
+/// A lingering effect of a hit, damaging or hindering an entity for a while
+#[derive(Clone, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    duration: Timer,
+    /// time between ticks of damage
+    interval: Timer,
+    /// damage on every tick, for each stack
+    pub damage: f32,
+    /// movement speed and fire rate, as a multiple of the usual
+    pub slow: f32,
+    pub stacking: Stacking,
+    stacks: u32,
+}
+
+impl StatusEffect {
+    pub fn new(kind: StatusKind, duration: f32, interval: f32) -> Self {
+        Self {
+            kind,
+            duration: Timer::from_seconds(duration, false),
+            interval: Timer::from_seconds(interval, true),
+            damage: 0.0,
+            slow: 1.0,
+            stacking: Stacking::Refresh,
+            stacks: 1,
+        }
+    }
+
+    pub fn with_damage(mut self, damage: f32) -> Self {
+        self.damage = damage;
+        self
+    }
+
+    pub fn with_slow(mut self, slow: f32) -> Self {
+        self.slow = slow;
+        self
+    }
+
+    pub fn with_stacking(mut self, stacking: Stacking) -> Self {
+        self.stacking = stacking;
+        self
+    }
+
+    /// The usual effect of a kind, for weapons and hazards that just name it
+    pub fn of(kind: StatusKind) -> Self {
+        match kind {
+            StatusKind::Burn => Self::new(kind, 2.0, 0.5).with_damage(0.25),
+            StatusKind::Slow => Self::new(kind, 2.0, 1.0).with_slow(0.5),
+            StatusKind::Poison => Self::new(kind, 4.0, 1.0)
+                .with_damage(0.1)
+                .with_stacking(Stacking::Stack(5)),
+            StatusKind::Stun => Self::new(kind, 0.5, 1.0)
+                .with_slow(0.0)
+                .with_stacking(Stacking::Ignore),
+        }
+    }
+
+    pub fn state(&self) -> StatusEffectState {
+        StatusEffectState {
+            kind: self.kind,
+            duration: TimerState::from_timer(&self.duration),
+            interval: TimerState::from_timer(&self.interval),
+            damage: self.damage,
+            slow: self.slow,
+            stacking: self.stacking,
+            stacks: self.stacks,
+        }
+    }
+
+    pub fn from_state(state: &StatusEffectState) -> Self {
+        Self {
+            kind: state.kind,
+            duration: state.duration.to_timer(false),
+            interval: state.interval.to_timer(true),
+            damage: state.damage,
+            slow: state.slow,
+            stacking: state.stacking,
+            stacks: state.stacks,
+        }
+    }
+
+    // damage dealt over the delta
+    fn tick(&mut self, delta: std::time::Duration) -> f32 {
+        self.duration.tick(delta);
+        self.interval.tick(delta);
+
+        self.interval.times_finished_this_tick() as f32 * self.damage * self.stacks as f32
+    }
+}
+
+/// What's saved of a status effect
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusEffectState {
+    pub kind: StatusKind,
+    pub duration: TimerState,
+    pub interval: TimerState,
+    pub damage: f32,
+    pub slow: f32,
+    pub stacking: Stacking,
+    pub stacks: u32,
+}
+
+/// Status effects on an entity
+#[derive(Component, Clone, Debug, Default)]
+pub struct Statuses {
+    effects: Vec<StatusEffect>,
+}
+
+impl Statuses {
+    pub fn state(&self) -> Vec<StatusEffectState> {
+        self.effects.iter().map(StatusEffect::state).collect()
+    }
+
+    pub fn from_state(state: &[StatusEffectState]) -> Self {
+        Self {
+            effects: state.iter().map(StatusEffect::from_state).collect(),
+        }
+    }
+
+    pub fn apply(&mut self, effect: StatusEffect) {
+        let current = match self.effects.iter_mut().find(|e| e.kind == effect.kind) {
+            Some(current) => current,
+            None => {
+                self.effects.push(effect);
+                return;
+            }
+        };
+
+        match current.stacking {
+            Stacking::Refresh => *current = effect,
+            Stacking::Stack(max) => {
+                current.stacks = (current.stacks + 1).min(max);
+                current.duration.reset();
+            }
+            Stacking::Ignore => {}
+        }
+    }
+
+    /// Movement speed and fire rate, as a multiple of the usual
+    pub fn slow(&self) -> f32 {
+        self.effects.iter().map(|effect| effect.slow).product()
+    }
+
+    pub fn is_stunned(&self) -> bool {
+        self.effects
+            .iter()
+            .any(|effect| effect.kind == StatusKind::Stun)
+    }
+}
+
+// deals damage over time and drops effects that ran out
+fn status_system(
+    mut status_query: Query<(&mut Statuses, Option<&mut Health>)>,
+    time: Res<FixedTime>,
+) {
+    for (mut statuses, mut health) in status_query.iter_mut() {
+        for effect in statuses.effects.iter_mut() {
+            let damage = effect.tick(time.delta());
+            if let (true, Some(health)) = (damage > 0.0, health.as_mut()) {
+                health.damage(damage);
+            }
+        }
+
+        statuses
+            .effects
+            .retain(|effect| !effect.duration.finished());
+    }
+}
+
+// stunned players can't do anything
+fn status_input_system(mut input_query: Query<(&Statuses, &mut PlayerInput)>) {
+    for (statuses, mut input) in input_query.iter_mut() {
+        if statuses.is_stunned() {
+            *input = PlayerInput {
+                aim: input.aim,
+                ..Default::default()
+            };
+        }
+    }
+}
+
+// slowed entities move slower
+fn status_movement_system(mut controller_query: Query<(&Statuses, &mut MovementController)>) {
+    for (statuses, mut controller) in controller_query.iter_mut() {
+        let slow = statuses.slow();
+        if slow != 1.0 {
+            controller.input *= slow;
+        }
+    }
+}
+
+/// Status system label, systems applying effects run before it and systems reading them after
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StatusSystem;
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_step_system_to_stage(
+            GameplayStage,
+            status_system.label(StatusSystem).before(HealthSystem),
+        )
+        // stuns from this step's hits take hold on the next one
+        .add_step_system_to_stage(
+            GameplayStage,
+            status_input_system
+                .after(InputSystem)
+                .before(player_system)
+                .before(BulletSystem),
+        )
+        .add_step_system_to_stage(
+            GameplayStage,
+            status_movement_system
+                .after(StatusSystem)
+                .after(player_system)
+                .after(shield_system)
+                .before(movement_system),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn seconds(seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds)
+    }
+
+    #[test]
+    fn refreshing_replaces_the_effect() {
+        let mut statuses = Statuses::default();
+        statuses.apply(StatusEffect::of(StatusKind::Slow));
+        statuses.effects[0].tick(seconds(1.5));
+
+        statuses.apply(StatusEffect::of(StatusKind::Slow).with_slow(0.25));
+
+        assert_eq!(statuses.effects.len(), 1);
+        assert_eq!(statuses.slow(), 0.25);
+        assert_eq!(statuses.effects[0].duration.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn stacks_add_up_to_the_limit_and_restart_the_duration() {
+        let mut statuses = Statuses::default();
+        for _ in 0..3 {
+            statuses.apply(StatusEffect::of(StatusKind::Poison));
+        }
+        statuses.effects[0].tick(seconds(3.5));
+        assert_eq!(statuses.effects[0].stacks, 3);
+
+        for _ in 0..10 {
+            statuses.apply(StatusEffect::of(StatusKind::Poison));
+        }
+
+        assert_eq!(statuses.effects.len(), 1);
+        assert_eq!(statuses.effects[0].stacks, 5);
+        assert_eq!(statuses.effects[0].duration.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn ignored_effects_leave_the_first_one() {
+        let mut statuses = Statuses::default();
+        statuses.apply(StatusEffect::of(StatusKind::Stun));
+        statuses.effects[0].tick(seconds(0.25));
+
+        statuses.apply(StatusEffect::of(StatusKind::Stun));
+
+        assert_eq!(statuses.effects.len(), 1);
+        assert_eq!(statuses.effects[0].duration.elapsed(), seconds(0.25));
+        assert!(statuses.is_stunned());
+    }
+
+    #[test]
+    fn kinds_are_kept_apart() {
+        let mut statuses = Statuses::default();
+        statuses.apply(StatusEffect::of(StatusKind::Slow));
+        statuses.apply(StatusEffect::of(StatusKind::Stun));
+
+        assert_eq!(statuses.effects.len(), 2);
+        assert_eq!(statuses.slow(), 0.0);
+    }
+
+    #[test]
+    fn ticks_deal_damage_per_interval_and_stack() {
+        let mut effect = StatusEffect::new(StatusKind::Burn, 2.0, 0.5).with_damage(0.25);
+
+        assert_eq!(effect.tick(seconds(0.4)), 0.0);
+        assert_eq!(effect.tick(seconds(0.2)), 0.25);
+        // a long step can cover more than one interval
+        assert_eq!(effect.tick(seconds(1.0)), 0.5);
+        assert!(!effect.duration.finished());
+
+        effect.stacks = 3;
+        assert_eq!(effect.tick(seconds(0.5)), 0.75);
+        assert!(effect.duration.finished());
+    }
+
+    #[test]
+    fn effects_round_trip_through_their_state() {
+        let mut statuses = Statuses::default();
+        statuses.apply(StatusEffect::of(StatusKind::Poison));
+        statuses.apply(StatusEffect::of(StatusKind::Poison));
+        statuses.effects[0].tick(seconds(1.25));
+
+        let restored = Statuses::from_state(&statuses.state());
+        let (before, after) = (&statuses.effects[0], &restored.effects[0]);
+
+        assert_eq!(after.kind, StatusKind::Poison);
+        assert_eq!(after.stacks, 2);
+        assert_eq!(after.stacking, before.stacking);
+        assert_eq!(after.duration.elapsed(), before.duration.elapsed());
+        assert_eq!(after.interval.elapsed(), before.interval.elapsed());
+    }
+}
@@ -39,7 +39,7 @@ pub fn spawn_wall_colliders(
         if let Ok(grandparent) = parent_query.get(parent.get()) {
             level_to_wall_locations
                 .entry(grandparent.get())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(grid_coords);
         }
     });